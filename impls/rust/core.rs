@@ -1,27 +1,32 @@
+use std::cmp::Ordering;
 use std::fs::File;
 use std::io::Read;
-use std::rc::Rc;
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use num_bigint::BigInt as BigInteger;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
 extern crate rustyline;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::DefaultEditor;
 
+use self::Nums::{BigInts, Floats, Ints, Ratios};
 use crate::printer::pr_seq;
-use crate::reader::read_str;
-use crate::types::MalErr::ErrMalVal;
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{MalArgs, MalRet, MalVal, _assoc, _dissoc, atom, error, func, hash_map};
-
-macro_rules! fn_t_int_int {
-    ($ret:ident, $fn:expr) => {{
-        |a: MalArgs| match (a[0].clone(), a[1].clone()) {
-            (Int(a0), Int(a1)) => Ok($ret($fn(a0, a1))),
-            _ => error("expecting (int,int) args"),
-        }
-    }};
-}
+use crate::reader::{read_forms, read_str, read_str_from};
+use crate::shared::Rc;
+use crate::types::MalErr::{ErrKind, ErrMalVal};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Exception, Float, Func, Hash, Int, Keyword, LazySeq, List, MalFunc, Nil,
+    Ratio, Set, Str, Sym, Vector,
+};
+use crate::types::{
+    _assoc, _conj_set, _dissoc, atom, bigint, error, ex_info, func, hash_map, hash_set, integer,
+    keyword, lazy_cons, lazy_seq, ratio, type_error, MalArgs, MalErr, MalRet, MalSet, MalVal,
+    MalVec, VARIADIC,
+};
 
 macro_rules! fn_is_type {
   ($($ps:pat),*) => {{
@@ -39,23 +44,324 @@ macro_rules! fn_str {
     ($fn:expr) => {{
         |a: MalArgs| match a[0].clone() {
             Str(a0) => $fn(a0),
-            _ => error("expecting (str) arg"),
+            _ => type_error("expecting (str) arg"),
         }
     }};
 }
 
+// The arguments of a binary numeric operation, promoted to a common
+// representation along the tower int -> big integer -> ratio -> float.
+enum Nums {
+    Ints(i64, i64),
+    BigInts(BigInteger, BigInteger),
+    Ratios(BigRational, BigRational),
+    Floats(f64, f64),
+}
+
+fn to_f64<T: ToPrimitive>(n: &T) -> f64 {
+    n.to_f64().unwrap_or(f64::NAN)
+}
+
+fn to_ratio(a: &MalVal) -> BigRational {
+    match a {
+        Int(i) => BigRational::from_integer(BigInteger::from(*i)),
+        BigInt(n) => BigRational::from_integer((**n).clone()),
+        Ratio(r) => (**r).clone(),
+        _ => unreachable!("to_ratio of non-rational"),
+    }
+}
+
+fn promote(a: &MalVal, b: &MalVal) -> Result<Nums, MalErr> {
+    match (a, b) {
+        (Int(i), Int(j)) => Ok(Ints(*i, *j)),
+        (Int(i), BigInt(y)) => Ok(BigInts(BigInteger::from(*i), (**y).clone())),
+        (BigInt(x), Int(j)) => Ok(BigInts((**x).clone(), BigInteger::from(*j))),
+        (BigInt(x), BigInt(y)) => Ok(BigInts((**x).clone(), (**y).clone())),
+        (Ratio(_), Int(_) | BigInt(_) | Ratio(_)) | (Int(_) | BigInt(_), Ratio(_)) => {
+            Ok(Ratios(to_ratio(a), to_ratio(b)))
+        }
+        (Int(i), Float(y)) => Ok(Floats(*i as f64, *y)),
+        (Float(x), Int(j)) => Ok(Floats(*x, *j as f64)),
+        (BigInt(x), Float(y)) => Ok(Floats(to_f64(&**x), *y)),
+        (Float(x), BigInt(y)) => Ok(Floats(*x, to_f64(&**y))),
+        (Ratio(x), Float(y)) => Ok(Floats(to_f64(&**x), *y)),
+        (Float(x), Ratio(y)) => Ok(Floats(*x, to_f64(&**y))),
+        (Float(x), Float(y)) => Ok(Floats(*x, *y)),
+        _ => Err(ErrKind("type-error", "expecting number args".to_string())),
+    }
+}
+
+// Int results that overflow are recomputed as big integers.
+macro_rules! checked_int {
+    ($i:expr, $j:expr, $checked:ident, $op:tt) => {
+        match $i.$checked($j) {
+            Some(n) => Int(n),
+            None => bigint(BigInteger::from($i) $op BigInteger::from($j)),
+        }
+    };
+}
+
+fn add(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(i, j) => Ok(checked_int!(i, j, checked_add, +)),
+        BigInts(x, y) => Ok(bigint(x + y)),
+        Ratios(x, y) => Ok(ratio(x + y)),
+        Floats(x, y) => Ok(Float(x + y)),
+    }
+}
+
+fn sub(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(i, j) => Ok(checked_int!(i, j, checked_sub, -)),
+        BigInts(x, y) => Ok(bigint(x - y)),
+        Ratios(x, y) => Ok(ratio(x - y)),
+        Floats(x, y) => Ok(Float(x - y)),
+    }
+}
+
+fn mul(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(i, j) => Ok(checked_int!(i, j, checked_mul, *)),
+        BigInts(x, y) => Ok(bigint(x * y)),
+        Ratios(x, y) => Ok(ratio(x * y)),
+        Floats(x, y) => Ok(Float(x * y)),
+    }
+}
+
+// Exact division: integers that do not divide evenly yield a ratio.
+fn div(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(_, 0) => error("Divide by zero"),
+        Ints(i, j) if i.wrapping_rem(j) == 0 => Ok(checked_int!(i, j, checked_div, /)),
+        Ints(i, j) => Ok(ratio(BigRational::new(i.into(), j.into()))),
+        BigInts(_, ref y) if y.is_zero() => error("Divide by zero"),
+        BigInts(ref x, ref y) if (x % y).is_zero() => Ok(bigint(x / y)),
+        BigInts(x, y) => Ok(ratio(BigRational::new(x, y))),
+        Ratios(_, ref y) if y.is_zero() => error("Divide by zero"),
+        Ratios(x, y) => Ok(ratio(x / y)),
+        Floats(x, y) => Ok(Float(x / y)),
+    }
+}
+
+// Folds `op` over the arguments left to right. A single argument is
+// combined with `identity`, so `(- x)` negates and `(/ x)` inverts.
+fn arith(a: MalArgs, identity: MalVal, op: fn(&MalVal, &MalVal) -> MalRet) -> MalRet {
+    match a.split_first() {
+        Some((x, [])) => op(&identity, x),
+        Some((x, rest)) => rest.iter().try_fold(x.clone(), |acc, y| op(&acc, y)),
+        None => Ok(identity),
+    }
+}
+
+// True when `pred` holds for the ordering of every adjacent pair of
+// arguments. Comparisons involving NaN are always false.
+fn compare(a: MalArgs, pred: fn(Ordering) -> bool) -> MalRet {
+    for w in a.windows(2) {
+        let ord = match promote(&w[0], &w[1])? {
+            Ints(i, j) => i.partial_cmp(&j),
+            BigInts(x, y) => x.partial_cmp(&y),
+            Ratios(x, y) => x.partial_cmp(&y),
+            Floats(x, y) => x.partial_cmp(&y),
+        };
+        match ord {
+            Some(o) if pred(o) => continue,
+            _ => return Ok(Bool(false)),
+        }
+    }
+    Ok(Bool(true))
+}
+
+fn quot(a: MalArgs) -> MalRet {
+    match promote(&a[0], &a[1])? {
+        Ints(_, 0) => error("Divide by zero"),
+        Ints(i, j) => Ok(checked_int!(i, j, checked_div, /)),
+        BigInts(_, ref y) if y.is_zero() => error("Divide by zero"),
+        BigInts(x, y) => Ok(bigint(x / y)),
+        Ratios(_, ref y) if y.is_zero() => error("Divide by zero"),
+        Ratios(x, y) => Ok(integer((x / y).trunc().to_integer())),
+        Floats(_, 0.0) => error("Divide by zero"),
+        Floats(x, y) => Ok(Float((x / y).trunc())),
+    }
+}
+
+// Remainder of truncating division; takes the sign of the dividend.
+fn rem(a: MalArgs) -> MalRet {
+    match promote(&a[0], &a[1])? {
+        Ints(_, 0) => error("Divide by zero"),
+        Ints(i, j) => Ok(Int(i.wrapping_rem(j))),
+        BigInts(_, ref y) if y.is_zero() => error("Divide by zero"),
+        BigInts(x, y) => Ok(bigint(x % y)),
+        Ratios(_, ref y) if y.is_zero() => error("Divide by zero"),
+        Ratios(x, y) => Ok(ratio(x % y)),
+        Floats(_, 0.0) => error("Divide by zero"),
+        Floats(x, y) => Ok(Float(x % y)),
+    }
+}
+
+// Modulus of floored division; takes the sign of the divisor.
+fn modulo(a: MalArgs) -> MalRet {
+    match promote(&a[0], &a[1])? {
+        Ints(_, 0) => error("Divide by zero"),
+        Ints(i, j) => {
+            let m = i.wrapping_rem(j);
+            Ok(Int(if m != 0 && (m < 0) != (j < 0) {
+                m + j
+            } else {
+                m
+            }))
+        }
+        BigInts(_, ref y) if y.is_zero() => error("Divide by zero"),
+        BigInts(x, y) => Ok(bigint(x.mod_floor(&y))),
+        Ratios(_, ref y) if y.is_zero() => error("Divide by zero"),
+        Ratios(x, y) => {
+            let m = &x % &y;
+            Ok(ratio(
+                if !m.is_zero() && m.is_negative() != y.is_negative() {
+                    m + y
+                } else {
+                    m
+                },
+            ))
+        }
+        Floats(_, 0.0) => error("Divide by zero"),
+        Floats(x, y) => {
+            let m = x % y;
+            Ok(Float(if m != 0.0 && (m < 0.0) != (y < 0.0) {
+                m + y
+            } else {
+                m
+            }))
+        }
+    }
+}
+
+fn int(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(i) => Ok(Int(i)),
+        BigInt(ref n) => match n.to_i64() {
+            Some(i) => Ok(Int(i)),
+            None => error("int: value out of range"),
+        },
+        Ratio(ref r) => match r.trunc().to_integer().to_i64() {
+            Some(i) => Ok(Int(i)),
+            None => error("int: value out of range"),
+        },
+        Float(f) => match f.trunc().to_i64() {
+            Some(i) => Ok(Int(i)),
+            None => error("int: value out of range"),
+        },
+        _ => type_error("int: expecting number arg"),
+    }
+}
+
+fn big_int(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(i) => Ok(bigint(BigInteger::from(i))),
+        BigInt(_) => Ok(a[0].clone()),
+        Ratio(ref r) => Ok(bigint(r.trunc().to_integer())),
+        Float(f) => match BigInteger::from_f64(f.trunc()) {
+            Some(n) => Ok(bigint(n)),
+            None => error("bigint: value out of range"),
+        },
+        _ => type_error("bigint: expecting number arg"),
+    }
+}
+
+fn double(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(i) => Ok(Float(i as f64)),
+        BigInt(ref n) => Ok(Float(to_f64(&**n))),
+        Ratio(ref r) => Ok(Float(to_f64(&**r))),
+        Float(f) => Ok(Float(f)),
+        _ => type_error("double: expecting number arg"),
+    }
+}
+
+fn numerator(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(_) | BigInt(_) => Ok(a[0].clone()),
+        Ratio(ref r) => Ok(integer(r.numer().clone())),
+        _ => type_error("numerator: expecting rational arg"),
+    }
+}
+
+fn denominator(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(_) | BigInt(_) => Ok(Int(1)),
+        Ratio(ref r) => Ok(integer(r.denom().clone())),
+        _ => type_error("denominator: expecting rational arg"),
+    }
+}
+
+// Converts a float to the ratio of its shortest decimal representation,
+// so that `(rationalize 0.1)` is 1/10 rather than the exact binary value.
+fn rationalize(a: MalArgs) -> MalRet {
+    match a[0] {
+        Float(f) if !f.is_finite() => type_error("rationalize: expecting finite number"),
+        Float(f) => {
+            let repr = format!("{:?}", f);
+            let (mantissa, exp) = match repr.split_once('e') {
+                Some((m, e)) => (m, e.parse::<i32>().unwrap()),
+                None => (&repr[..], 0),
+            };
+            let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+            let digits: BigInteger = format!("{}{}", int_part, frac_part).parse().unwrap();
+            let exp = exp - frac_part.len() as i32;
+            let ten = BigRational::from_integer(BigInteger::from(10));
+            Ok(ratio(BigRational::from_integer(digits) * ten.pow(exp)))
+        }
+        Int(_) | BigInt(_) | Ratio(_) => Ok(a[0].clone()),
+        _ => type_error("rationalize: expecting number arg"),
+    }
+}
+
+fn keyword_fn(a: MalArgs) -> MalRet {
+    match (&a[0], a.get(1)) {
+        (Keyword(_), None) => Ok(a[0].clone()),
+        (Str(name), None) => Ok(keyword(name)),
+        (Str(ns), Some(Str(name))) => Ok(keyword(&format!("{}/{}", ns, name))),
+        _ => type_error("keyword: expecting string args"),
+    }
+}
+
+// Splits a symbol or keyword name into its namespace and local name.
+fn split_ns(s: &str) -> (Option<&str>, &str) {
+    match s.find('/') {
+        Some(i) if i > 0 && s.len() > 1 => (Some(&s[..i]), &s[i + 1..]),
+        _ => (None, s),
+    }
+}
+
+fn name(a: MalArgs) -> MalRet {
+    match a[0] {
+        Str(ref s) => Ok(Str(s.to_string())),
+        Sym(ref s) => Ok(Str(split_ns(s).1.to_string())),
+        Keyword(ref k) => Ok(Str(split_ns(k).1.to_string())),
+        _ => type_error("name: expecting string, symbol or keyword"),
+    }
+}
+
+fn namespace(a: MalArgs) -> MalRet {
+    let ns = match a[0] {
+        Sym(ref s) => split_ns(s).0,
+        Keyword(ref k) => split_ns(k).0,
+        _ => return type_error("namespace: expecting symbol or keyword"),
+    };
+    Ok(ns.map_or(Nil, |ns| Str(ns.to_string())))
+}
+
 fn symbol(a: MalArgs) -> MalRet {
     match a[0] {
         Str(ref s) => Ok(Sym(s.to_string())),
-        _ => error("illegal symbol call"),
+        _ => type_error("illegal symbol call"),
     }
 }
 
 fn readline(a: MalArgs) -> MalRet {
     lazy_static! {
-        static ref RL: Mutex<Editor<()>> = Mutex::new(Editor::<()>::new());
+        static ref RL: Mutex<DefaultEditor> = Mutex::new(DefaultEditor::new().unwrap());
     }
-    //let mut rl = Editor::<()>::new();
+    //let mut rl = DefaultEditor::new().unwrap();
 
     match a[0] {
         Str(ref p) => {
@@ -100,69 +406,75 @@ fn time_ms(_a: MalArgs) -> MalRet {
 fn get(a: MalArgs) -> MalRet {
     match (a[0].clone(), a[1].clone()) {
         (Nil, _) => Ok(Nil),
-        (Hash(ref hm, _), Str(ref s)) => match hm.get(s) {
+        (Hash(ref hm, _), ref k) => match hm.get(k) {
             Some(mv) => Ok(mv.clone()),
             None => Ok(Nil),
         },
-        _ => error("illegal get args"),
+        _ => type_error("illegal get args"),
     }
 }
 
 fn assoc(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => _assoc((**hm).clone(), a[1..].to_vec()),
-        _ => error("assoc on non-Hash Map"),
+        Hash(ref hm, _) => _assoc(hm.clone(), a[1..].to_vec()),
+        _ => type_error("assoc on non-Hash Map"),
     }
 }
 
 fn dissoc(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => _dissoc((**hm).clone(), a[1..].to_vec()),
-        _ => error("dissoc on non-Hash Map"),
+        Hash(ref hm, _) => _dissoc(hm.clone(), a[1..].to_vec()),
+        _ => type_error("dissoc on non-Hash Map"),
     }
 }
 
 fn contains_q(a: MalArgs) -> MalRet {
     match (a[0].clone(), a[1].clone()) {
-        (Hash(ref hm, _), Str(ref s)) => Ok(Bool(hm.contains_key(s))),
-        _ => error("illegal get args"),
+        (Hash(ref hm, _), ref k) => Ok(Bool(hm.contains_key(k))),
+        (Set(ref s, _), ref k) => Ok(Bool(s.contains(k))),
+        _ => type_error("illegal get args"),
     }
 }
 
 fn keys(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => Ok(list!(hm.keys().map(|k| { Str(k.to_string()) }).collect())),
-        _ => error("keys requires Hash Map"),
+        Hash(ref hm, _) => Ok(list!(hm.keys().cloned().collect::<MalVec>())),
+        _ => type_error("keys requires Hash Map"),
     }
 }
 
 fn vals(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => Ok(list!(hm.values().map(|v| { v.clone() }).collect())),
-        _ => error("keys requires Hash Map"),
+        Hash(ref hm, _) => Ok(list!(hm.values().cloned().collect::<MalVec>())),
+        _ => type_error("keys requires Hash Map"),
     }
 }
 
-fn cons(a: MalArgs) -> MalRet {
-    match a[1].clone() {
-        List(v, _) | Vector(v, _) => {
-            let mut new_v = vec![a[0].clone()];
-            new_v.extend_from_slice(&v);
-            Ok(list!(new_v.to_vec()))
+// cons and concat take their list arguments by value, so that a list built
+// by an inner call, as quasiquote makes, is extended in place, not copied.
+pub(crate) fn cons(mut a: MalArgs) -> MalRet {
+    match a.pop().unwrap() {
+        List(mut v, _) | Vector(mut v, _) => {
+            v.push_front(a[0].clone());
+            Ok(list!(v))
         }
-        _ => error("cons expects seq as second arg"),
+        seq @ LazySeq(_) => Ok(lazy_cons(a[0].clone(), seq)),
+        _ => type_error("cons expects seq as second arg"),
     }
 }
 
-fn concat(a: MalArgs) -> MalRet {
-    let mut new_v = vec![];
-    for seq in a.iter() {
+pub(crate) fn concat(a: MalArgs) -> MalRet {
+    if a.iter().any(|seq| matches!(seq, LazySeq(_))) {
+        return Ok(concat_seq(a));
+    }
+    let mut new_v = MalVec::new();
+    for seq in a {
         match seq {
-            List(v, _) | Vector(v, _) => new_v.extend_from_slice(v),
-            _ => return error("non-seq passed to concat"),
+            List(v, _) | Vector(v, _) => new_v.append(v),
+            _ => return type_error("non-seq passed to concat"),
         }
     }
-    Ok(list!(new_v.to_vec()))
+    Ok(list!(new_v))
 }
 
 fn nth(a: MalArgs) -> MalRet {
@@ -173,16 +485,24 @@ fn nth(a: MalArgs) -> MalRet {
             }
             Ok(seq[idx as usize].clone())
         }
-        _ => error("invalid args to nth"),
+        (LazySeq(_), Int(idx)) | (Set(..), Int(idx)) if idx >= 0 => {
+            match a[0].elements().nth(idx as usize) {
+                Some(v) => v,
+                None => error("nth: index out of range"),
+            }
+        }
+        (LazySeq(_), Int(_)) | (Set(..), Int(_)) => error("nth: index out of range"),
+        _ => type_error("invalid args to nth"),
     }
 }
 
 fn first(a: MalArgs) -> MalRet {
     match a[0].clone() {
-        List(ref seq, _) | Vector(ref seq, _) if seq.len() == 0 => Ok(Nil),
+        List(ref seq, _) | Vector(ref seq, _) if seq.is_empty() => Ok(Nil),
         List(ref seq, _) | Vector(ref seq, _) => Ok(seq[0].clone()),
+        LazySeq(_) | Set(..) => Ok(a[0].uncons()?.map(|(first, _)| first).unwrap_or(Nil)),
         Nil => Ok(Nil),
-        _ => error("invalid args to first"),
+        _ => type_error("invalid args to first"),
     }
 }
 
@@ -190,13 +510,17 @@ fn rest(a: MalArgs) -> MalRet {
     match a[0].clone() {
         List(ref seq, _) | Vector(ref seq, _) => {
             if seq.len() > 1 {
-                Ok(list!(seq[1..].to_vec()))
+                Ok(list!(seq.skip(1)))
             } else {
                 Ok(list![])
             }
         }
+        LazySeq(_) | Set(..) => match a[0].uncons()? {
+            Some((_, Nil)) | None => Ok(list![]),
+            Some((_, rest)) => Ok(rest),
+        },
         Nil => Ok(list![]),
-        _ => error("invalid args to first"),
+        _ => type_error("invalid args to rest"),
     }
 }
 
@@ -205,139 +529,536 @@ fn apply(a: MalArgs) -> MalRet {
         List(ref v, _) | Vector(ref v, _) => {
             let f = &a[0];
             let mut fargs = a[1..a.len() - 1].to_vec();
-            fargs.extend_from_slice(&v);
+            fargs.extend(v.iter().cloned());
             f.apply(fargs)
         }
-        _ => error("apply called with non-seq"),
+        LazySeq(_) => {
+            let f = &a[0];
+            let mut fargs = a[1..a.len() - 1].to_vec();
+            fargs.extend(a[a.len() - 1].realize()?);
+            f.apply(fargs)
+        }
+        _ => type_error("apply called with non-seq"),
     }
 }
 
 fn map(a: MalArgs) -> MalRet {
     match a[1] {
-        List(ref v, _) | Vector(ref v, _) => {
+        List(..) | Vector(..) | Set(..) => {
             let mut res = vec![];
-            for mv in v.iter() {
-                res.push(a[0].apply(vec![mv.clone()])?)
+            for mv in a[1].elements() {
+                res.push(a[0].apply(vec![mv?])?)
             }
             Ok(list!(res))
         }
-        _ => error("map called with non-seq"),
+        LazySeq(_) => Ok(map_seq(a[0].clone(), a[1].clone())),
+        _ => type_error("map called with non-seq"),
     }
 }
 
 fn conj(a: MalArgs) -> MalRet {
     match a[0] {
         List(ref v, _) => {
-            let sl = a[1..]
-                .iter()
-                .rev()
-                .map(|a| a.clone())
-                .collect::<Vec<MalVal>>();
-            Ok(list!([&sl[..], v].concat()))
+            let mut new_v = v.clone();
+            for mv in a[1..].iter() {
+                new_v.push_front(mv.clone());
+            }
+            Ok(list!(new_v))
+        }
+        Vector(ref v, _) => {
+            let mut new_v = v.clone();
+            new_v.extend(a[1..].iter().cloned());
+            Ok(vector!(new_v))
         }
-        Vector(ref v, _) => Ok(vector!([v, &a[1..]].concat())),
-        _ => error("conj: called with non-seq"),
+        Set(ref s, _) => _conj_set(s.clone(), a[1..].to_vec()),
+        LazySeq(_) => Ok(a[1..]
+            .iter()
+            .fold(a[0].clone(), |acc, mv| lazy_cons(mv.clone(), acc))),
+        _ => type_error("conj: called with non-seq"),
     }
 }
 
 fn seq(a: MalArgs) -> MalRet {
     match a[0] {
-        List(ref v, _) | Vector(ref v, _) if v.len() == 0 => Ok(Nil),
-        List(ref v, _) | Vector(ref v, _) => Ok(list!(v.to_vec())),
-        Str(ref s) if s.len() == 0 => Ok(Nil),
-        Str(ref s) if !a[0].keyword_q() => {
-            Ok(list!(s.chars().map(|c| { Str(c.to_string()) }).collect()))
+        List(ref v, _) | Vector(ref v, _) if v.is_empty() => Ok(Nil),
+        List(ref v, _) | Vector(ref v, _) => Ok(list!(v.clone())),
+        LazySeq(_) if a[0].uncons()?.is_none() => Ok(Nil),
+        LazySeq(_) => Ok(a[0].clone()),
+        Set(ref s, _) if s.is_empty() => Ok(Nil),
+        Set(ref s, _) => Ok(list!(s.iter().cloned().collect::<MalVec>())),
+        Str(ref s) if s.is_empty() => Ok(Nil),
+        Str(ref s) => Ok(list!(s
+            .chars()
+            .map(|c| { Str(c.to_string()) })
+            .collect::<MalVec>())),
+        Nil => Ok(Nil),
+        _ => type_error("seq: called with non-seq"),
+    }
+}
+
+// Lazy sequences. Each function returns an unrealized sequence whose
+// thunk does one step of the work, leaving the rest as another lazy seq.
+
+fn truthy(mv: &MalVal) -> bool {
+    !matches!(mv, Nil | Bool(false))
+}
+
+fn lt(a: &MalVal, b: &MalVal) -> Result<bool, MalErr> {
+    Ok(compare(vec![a.clone(), b.clone()], |o| o == Ordering::Less)? == Bool(true))
+}
+
+fn range_from(start: MalVal, end: Option<MalVal>, step: MalVal) -> MalVal {
+    lazy_seq(move || {
+        if let Some(ref end) = end {
+            let more = if lt(&step, &Int(0))? {
+                lt(end, &start)?
+            } else {
+                lt(&start, end)?
+            };
+            if !more {
+                return Ok(Nil);
+            }
+        }
+        let next = add(&start, &step)?;
+        Ok(lazy_cons(
+            start.clone(),
+            range_from(next, end.clone(), step.clone()),
+        ))
+    })
+}
+
+fn range(a: MalArgs) -> MalRet {
+    let (start, end, step) = match a.len() {
+        0 => (Int(0), None, Int(1)),
+        1 => (Int(0), Some(a[0].clone()), Int(1)),
+        2 => (a[0].clone(), Some(a[1].clone()), Int(1)),
+        _ => (a[0].clone(), Some(a[1].clone()), a[2].clone()),
+    };
+    for n in [&start, &step].iter().cloned().chain(end.iter()) {
+        promote(n, &Int(0))?;
+    }
+    Ok(range_from(start, end, step))
+}
+
+fn iterate(f: MalVal, x: MalVal) -> MalVal {
+    let next = x.clone();
+    lazy_cons(
+        x,
+        lazy_seq(move || Ok(iterate(f.clone(), f.apply(vec![next.clone()])?))),
+    )
+}
+
+fn repeat(x: MalVal) -> MalVal {
+    let next = x.clone();
+    lazy_cons(x, lazy_seq(move || Ok(repeat(next.clone()))))
+}
+
+fn cycle(coll: MalVal, cur: MalVal) -> MalVal {
+    lazy_seq(move || {
+        let step = match cur.uncons()? {
+            None => coll.uncons()?,
+            step => step,
+        };
+        match step {
+            Some((first, rest)) => Ok(lazy_cons(first, cycle(coll.clone(), rest))),
+            None => Ok(Nil),
+        }
+    })
+}
+
+fn take(n: i64, coll: MalVal) -> MalVal {
+    lazy_seq(move || {
+        if n <= 0 {
+            return Ok(Nil);
+        }
+        match coll.uncons()? {
+            Some((first, rest)) => Ok(lazy_cons(first, take(n - 1, rest))),
+            None => Ok(Nil),
+        }
+    })
+}
+
+fn drop(n: i64, coll: MalVal) -> MalVal {
+    lazy_seq(move || {
+        let mut s = coll.clone();
+        for _ in 0..n {
+            s = match s.uncons()? {
+                Some((_, rest)) => rest,
+                None => return Ok(Nil),
+            };
+        }
+        Ok(s)
+    })
+}
+
+fn filter(pred: MalVal, coll: MalVal) -> MalVal {
+    lazy_seq(move || {
+        let mut s = coll.clone();
+        while let Some((first, rest)) = s.uncons()? {
+            if truthy(&pred.apply(vec![first.clone()])?) {
+                return Ok(lazy_cons(first, filter(pred.clone(), rest)));
+            }
+            s = rest;
+        }
+        Ok(Nil)
+    })
+}
+
+fn take_while(pred: MalVal, coll: MalVal) -> MalVal {
+    lazy_seq(move || match coll.uncons()? {
+        Some((first, rest)) if truthy(&pred.apply(vec![first.clone()])?) => {
+            Ok(lazy_cons(first, take_while(pred.clone(), rest)))
+        }
+        _ => Ok(Nil),
+    })
+}
+
+fn map_seq(f: MalVal, coll: MalVal) -> MalVal {
+    lazy_seq(move || match coll.uncons()? {
+        Some((first, rest)) => Ok(lazy_cons(f.apply(vec![first])?, map_seq(f.clone(), rest))),
+        None => Ok(Nil),
+    })
+}
+
+fn concat_seq(seqs: MalArgs) -> MalVal {
+    lazy_seq(move || {
+        for (i, seq) in seqs.iter().enumerate() {
+            if let Some((first, rest)) = seq.uncons()? {
+                let mut remaining = vec![rest];
+                remaining.extend_from_slice(&seqs[i + 1..]);
+                return Ok(lazy_cons(first, concat_seq(remaining)));
+            }
+        }
+        Ok(Nil)
+    })
+}
+
+fn count_arg(name: &str, n: &MalVal) -> Result<i64, MalErr> {
+    match n {
+        Int(n) => Ok(*n),
+        _ => Err(ErrKind(
+            "type-error",
+            format!("{}: count must be an integer", name),
+        )),
+    }
+}
+
+fn set(a: MalArgs) -> MalRet {
+    match a[0] {
+        Nil => hash_set(vec![]),
+        List(ref v, _) | Vector(ref v, _) => hash_set(v.iter().cloned().collect()),
+        Set(ref s, _) => Ok(Set(s.clone(), Rc::new(Nil))),
+        Hash(ref hm, _) => hash_set(
+            hm.iter()
+                .map(|(k, v)| vector![k.clone(), v.clone()])
+                .collect(),
+        ),
+        _ => type_error("set: called with non-seq"),
+    }
+}
+
+fn disj(a: MalArgs) -> MalRet {
+    match a[0] {
+        Set(ref s, _) => {
+            let mut new_s = s.clone();
+            for k in a[1..].iter() {
+                new_s.remove(k);
+            }
+            Ok(Set(new_s, Rc::new(Nil)))
         }
         Nil => Ok(Nil),
-        _ => error("seq: called with non-seq"),
+        _ => type_error("disj: called with non-set"),
+    }
+}
+
+fn sets(a: &MalArgs, name: &str) -> Result<Vec<MalSet>, MalErr> {
+    a.iter()
+        .map(|s| match s {
+            Set(s, _) => Ok(s.clone()),
+            _ => Err(ErrKind(
+                "type-error",
+                format!("{}: called with non-set", name),
+            )),
+        })
+        .collect()
+}
+
+fn union(a: MalArgs) -> MalRet {
+    let res = MalSet::unions(sets(&a, "union")?);
+    Ok(Set(res, Rc::new(Nil)))
+}
+
+fn intersection(a: MalArgs) -> MalRet {
+    let mut ss = sets(&a, "intersection")?.into_iter();
+    let first = ss.next().unwrap();
+    let res = ss.fold(first, |acc, s| acc.intersection(s));
+    Ok(Set(res, Rc::new(Nil)))
+}
+
+fn difference(a: MalArgs) -> MalRet {
+    let mut ss = sets(&a, "difference")?.into_iter();
+    let first = ss.next().unwrap();
+    let res = ss.fold(first, |acc, s| acc.relative_complement(s));
+    Ok(Set(res, Rc::new(Nil)))
+}
+
+fn ex_info_fn(a: MalArgs) -> MalRet {
+    match (&a[0], &a[1]) {
+        (Str(msg), Hash(..)) => Ok(ex_info(
+            msg.clone(),
+            a[1].clone(),
+            a.get(2).cloned().unwrap_or(Nil),
+        )),
+        _ => type_error("ex-info: expecting a message string and a data map"),
     }
 }
 
 pub fn ns() -> Vec<(&'static str, MalVal)> {
     vec![
-        ("=", func(|a| Ok(Bool(a[0] == a[1])))),
-        ("throw", func(|a| Err(ErrMalVal(a[0].clone())))),
-        ("nil?", func(fn_is_type!(Nil))),
-        ("true?", func(fn_is_type!(Bool(true)))),
-        ("false?", func(fn_is_type!(Bool(false)))),
-        ("symbol", func(symbol)),
-        ("symbol?", func(fn_is_type!(Sym(_)))),
         (
-            "string?",
-            func(fn_is_type!(Str(ref s) if !s.starts_with("\u{29e}"))),
+            "=",
+            func("=", 1, VARIADIC, |a| {
+                Ok(Bool(a.windows(2).all(|w| w[0] == w[1])))
+            }),
         ),
-        ("keyword", func(|a| a[0].keyword())),
         (
-            "keyword?",
-            func(fn_is_type!(Str(ref s) if s.starts_with("\u{29e}"))),
+            "throw",
+            func("throw", 1, 1, |a| Err(ErrMalVal(a[0].clone()))),
+        ),
+        ("ex-info", func("ex-info", 2, 3, ex_info_fn)),
+        (
+            "ex-message",
+            func("ex-message", 1, 1, |a| match a[0] {
+                Exception(ref ex) => Ok(Str(ex.message.clone())),
+                _ => Ok(Nil),
+            }),
+        ),
+        (
+            "ex-data",
+            func("ex-data", 1, 1, |a| match a[0] {
+                Exception(ref ex) => Ok(ex.data.clone()),
+                _ => Ok(Nil),
+            }),
+        ),
+        (
+            "ex-cause",
+            func("ex-cause", 1, 1, |a| match a[0] {
+                Exception(ref ex) => Ok(ex.cause.clone()),
+                _ => Ok(Nil),
+            }),
+        ),
+        ("nil?", func("nil?", 1, 1, fn_is_type!(Nil))),
+        ("true?", func("true?", 1, 1, fn_is_type!(Bool(true)))),
+        ("false?", func("false?", 1, 1, fn_is_type!(Bool(false)))),
+        ("symbol", func("symbol", 1, 1, symbol)),
+        ("symbol?", func("symbol?", 1, 1, fn_is_type!(Sym(_)))),
+        ("string?", func("string?", 1, 1, fn_is_type!(Str(_)))),
+        ("keyword", func("keyword", 1, 2, keyword_fn)),
+        ("keyword?", func("keyword?", 1, 1, fn_is_type!(Keyword(_)))),
+        ("name", func("name", 1, 1, name)),
+        ("namespace", func("namespace", 1, 1, namespace)),
+        (
+            "number?",
+            func(
+                "number?",
+                1,
+                1,
+                fn_is_type!(Int(_), BigInt(_), Ratio(_), Float(_)),
+            ),
+        ),
+        ("float?", func("float?", 1, 1, fn_is_type!(Float(_)))),
+        ("ratio?", func("ratio?", 1, 1, fn_is_type!(Ratio(_)))),
+        (
+            "rational?",
+            func("rational?", 1, 1, fn_is_type!(Int(_), BigInt(_), Ratio(_))),
         ),
-        ("number?", func(fn_is_type!(Int(_)))),
         (
             "fn?",
-            func(fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_))),
+            func(
+                "fn?",
+                1,
+                1,
+                fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func{..}),
+            ),
         ),
         (
             "macro?",
-            func(fn_is_type!(MalFunc{is_macro,..} if is_macro)),
+            func(
+                "macro?",
+                1,
+                1,
+                fn_is_type!(MalFunc{is_macro,..} if is_macro),
+            ),
+        ),
+        (
+            "pr-str",
+            func("pr-str", 0, VARIADIC, |a| {
+                Ok(Str(pr_seq(&a, true, "", "", " ")))
+            }),
+        ),
+        (
+            "str",
+            func("str", 0, VARIADIC, |a| {
+                Ok(Str(pr_seq(&a, false, "", "", "")))
+            }),
         ),
-        ("pr-str", func(|a| Ok(Str(pr_seq(&a, true, "", "", " "))))),
-        ("str", func(|a| Ok(Str(pr_seq(&a, false, "", "", ""))))),
         (
             "prn",
-            func(|a| {
+            func("prn", 0, VARIADIC, |a| {
                 println!("{}", pr_seq(&a, true, "", "", " "));
                 Ok(Nil)
             }),
         ),
         (
             "println",
-            func(|a| {
+            func("println", 0, VARIADIC, |a| {
                 println!("{}", pr_seq(&a, false, "", "", " "));
                 Ok(Nil)
             }),
         ),
-        ("read-string", func(fn_str!(|s| { read_str(s) }))),
-        ("readline", func(readline)),
-        ("slurp", func(fn_str!(|f| { slurp(f) }))),
-        ("<", func(fn_t_int_int!(Bool, |i, j| { i < j }))),
-        ("<=", func(fn_t_int_int!(Bool, |i, j| { i <= j }))),
-        (">", func(fn_t_int_int!(Bool, |i, j| { i > j }))),
-        (">=", func(fn_t_int_int!(Bool, |i, j| { i >= j }))),
-        ("+", func(fn_t_int_int!(Int, |i, j| { i + j }))),
-        ("-", func(fn_t_int_int!(Int, |i, j| { i - j }))),
-        ("*", func(fn_t_int_int!(Int, |i, j| { i * j }))),
-        ("/", func(fn_t_int_int!(Int, |i, j| { i / j }))),
-        ("time-ms", func(time_ms)),
-        ("sequential?", func(fn_is_type!(List(_, _), Vector(_, _)))),
-        ("list", func(|a| Ok(list!(a)))),
-        ("list?", func(fn_is_type!(List(_, _)))),
-        ("vector", func(|a| Ok(vector!(a)))),
-        ("vector?", func(fn_is_type!(Vector(_, _)))),
-        ("hash-map", func(|a| hash_map(a))),
-        ("map?", func(fn_is_type!(Hash(_, _)))),
-        ("assoc", func(assoc)),
-        ("dissoc", func(dissoc)),
-        ("get", func(get)),
-        ("contains?", func(contains_q)),
-        ("keys", func(keys)),
-        ("vals", func(vals)),
-        ("cons", func(cons)),
-        ("concat", func(concat)),
-        ("empty?", func(|a| a[0].empty_q())),
-        ("nth", func(nth)),
-        ("first", func(first)),
-        ("rest", func(rest)),
-        ("count", func(|a| a[0].count())),
-        ("apply", func(apply)),
-        ("map", func(map)),
-        ("conj", func(conj)),
-        ("seq", func(seq)),
-        ("meta", func(|a| a[0].get_meta())),
-        ("with-meta", func(|a| a[0].clone().with_meta(&a[1]))),
-        ("atom", func(|a| Ok(atom(&a[0])))),
-        ("atom?", func(fn_is_type!(Atom(_)))),
-        ("deref", func(|a| a[0].deref())),
-        ("reset!", func(|a| a[0].reset_bang(&a[1]))),
-        ("swap!", func(|a| a[0].swap_bang(&a[1..].to_vec()))),
+        (
+            "read-string",
+            func("read-string", 1, 2, |a| match (&a[0], a.get(1)) {
+                (Str(s), None) => read_str(s.clone()),
+                (Str(s), Some(Str(f))) => read_str_from(s.clone(), Some(f.clone())),
+                _ => error("read-string: expected a string and an optional file name"),
+            }),
+        ),
+        (
+            "read-all-string",
+            func("read-all-string", 1, 2, |a| match (&a[0], a.get(1)) {
+                (Str(s), None) => Ok(list!(read_forms(s, None).collect::<Result<MalVec, _>>()?)),
+                (Str(s), Some(Str(f))) => Ok(list!(
+                    read_forms(s, Some(f.clone())).collect::<Result<MalVec, _>>()?
+                )),
+                _ => error("read-all-string: expected a string and an optional file name"),
+            }),
+        ),
+        ("readline", func("readline", 1, 1, readline)),
+        ("slurp", func("slurp", 1, 1, fn_str!(|f| { slurp(f) }))),
+        (
+            "<",
+            func("<", 1, VARIADIC, |a| compare(a, |o| o == Ordering::Less)),
+        ),
+        (
+            "<=",
+            func("<=", 1, VARIADIC, |a| {
+                compare(a, |o| o != Ordering::Greater)
+            }),
+        ),
+        (
+            ">",
+            func(">", 1, VARIADIC, |a| compare(a, |o| o == Ordering::Greater)),
+        ),
+        (
+            ">=",
+            func(">=", 1, VARIADIC, |a| compare(a, |o| o != Ordering::Less)),
+        ),
+        ("+", func("+", 0, VARIADIC, |a| arith(a, Int(0), add))),
+        ("-", func("-", 1, VARIADIC, |a| arith(a, Int(0), sub))),
+        ("*", func("*", 0, VARIADIC, |a| arith(a, Int(1), mul))),
+        ("/", func("/", 1, VARIADIC, |a| arith(a, Int(1), div))),
+        ("quot", func("quot", 2, 2, quot)),
+        ("rem", func("rem", 2, 2, rem)),
+        ("mod", func("mod", 2, 2, modulo)),
+        ("int", func("int", 1, 1, int)),
+        ("bigint", func("bigint", 1, 1, big_int)),
+        ("double", func("double", 1, 1, double)),
+        ("numerator", func("numerator", 1, 1, numerator)),
+        ("denominator", func("denominator", 1, 1, denominator)),
+        ("rationalize", func("rationalize", 1, 1, rationalize)),
+        ("time-ms", func("time-ms", 0, 0, time_ms)),
+        (
+            "sequential?",
+            func(
+                "sequential?",
+                1,
+                1,
+                fn_is_type!(List(_, _), Vector(_, _), LazySeq(_)),
+            ),
+        ),
+        ("list", func("list", 0, VARIADIC, |a| Ok(list!(a)))),
+        ("list?", func("list?", 1, 1, fn_is_type!(List(_, _)))),
+        ("vector", func("vector", 0, VARIADIC, |a| Ok(vector!(a)))),
+        ("vector?", func("vector?", 1, 1, fn_is_type!(Vector(_, _)))),
+        ("hash-map", func("hash-map", 0, VARIADIC, hash_map)),
+        ("map?", func("map?", 1, 1, fn_is_type!(Hash(_, _)))),
+        ("assoc", func("assoc", 1, VARIADIC, assoc)),
+        ("dissoc", func("dissoc", 1, VARIADIC, dissoc)),
+        ("get", func("get", 2, 2, get)),
+        ("contains?", func("contains?", 2, 2, contains_q)),
+        ("keys", func("keys", 1, 1, keys)),
+        ("vals", func("vals", 1, 1, vals)),
+        ("set", func("set", 1, 1, set)),
+        ("set?", func("set?", 1, 1, fn_is_type!(Set(_, _)))),
+        ("disj", func("disj", 1, VARIADIC, disj)),
+        ("union", func("union", 0, VARIADIC, union)),
+        (
+            "intersection",
+            func("intersection", 1, VARIADIC, intersection),
+        ),
+        ("difference", func("difference", 1, VARIADIC, difference)),
+        ("range", func("range", 0, 3, range)),
+        (
+            "iterate",
+            func("iterate", 2, 2, |a| Ok(iterate(a[0].clone(), a[1].clone()))),
+        ),
+        (
+            "repeat",
+            func("repeat", 1, 2, |a| match a.len() {
+                1 => Ok(repeat(a[0].clone())),
+                _ => Ok(take(count_arg("repeat", &a[0])?, repeat(a[1].clone()))),
+            }),
+        ),
+        (
+            "cycle",
+            func("cycle", 1, 1, |a| Ok(cycle(a[0].clone(), a[0].clone()))),
+        ),
+        (
+            "take",
+            func("take", 2, 2, |a| {
+                Ok(take(count_arg("take", &a[0])?, a[1].clone()))
+            }),
+        ),
+        (
+            "drop",
+            func("drop", 2, 2, |a| {
+                Ok(drop(count_arg("drop", &a[0])?, a[1].clone()))
+            }),
+        ),
+        (
+            "filter",
+            func("filter", 2, 2, |a| Ok(filter(a[0].clone(), a[1].clone()))),
+        ),
+        (
+            "take-while",
+            func("take-while", 2, 2, |a| {
+                Ok(take_while(a[0].clone(), a[1].clone()))
+            }),
+        ),
+        ("cons", func("cons", 2, 2, cons)),
+        ("concat", func("concat", 0, VARIADIC, concat)),
+        ("empty?", func("empty?", 1, 1, |a| a[0].empty_q())),
+        ("nth", func("nth", 2, 2, nth)),
+        ("first", func("first", 1, 1, first)),
+        ("rest", func("rest", 1, 1, rest)),
+        ("count", func("count", 1, 1, |a| a[0].count())),
+        ("apply", func("apply", 2, VARIADIC, apply)),
+        ("map", func("map", 2, 2, map)),
+        ("conj", func("conj", 1, VARIADIC, conj)),
+        ("seq", func("seq", 1, 1, seq)),
+        ("meta", func("meta", 1, 1, |a| a[0].get_meta())),
+        (
+            "with-meta",
+            func("with-meta", 2, 2, |a| a[0].clone().with_meta(&a[1])),
+        ),
+        ("atom", func("atom", 1, 1, |a| Ok(atom(&a[0])))),
+        ("atom?", func("atom?", 1, 1, fn_is_type!(Atom(_)))),
+        ("deref", func("deref", 1, 1, |a| a[0].deref())),
+        ("reset!", func("reset!", 2, 2, |a| a[0].reset_bang(&a[1]))),
+        (
+            "swap!",
+            func("swap!", 2, VARIADIC, |a| a[0].swap_bang(&a[1..].to_vec())),
+        ),
     ]
 }
@@ -1,11 +1,10 @@
-use std::cell::RefCell;
-use std::rc::Rc;
 //use std::collections::HashMap;
 use fnv::FnvHashMap;
 
-use crate::types::MalErr::ErrString;
+use crate::shared::{Rc, RefCell};
+use crate::types::MalErr::{ErrKind, ErrString};
 use crate::types::MalVal::{List, Nil, Sym, Vector};
-use crate::types::{error, MalErr, MalRet, MalVal};
+use crate::types::{check_arity, error, MalErr, MalRet, MalVal, VARIADIC};
 
 #[derive(Debug)]
 pub struct EnvStruct {
@@ -21,7 +20,7 @@ pub type Env = Rc<EnvStruct>;
 pub fn env_new(outer: Option<Env>) -> Env {
     Rc::new(EnvStruct {
         data: RefCell::new(FnvHashMap::default()),
-        outer: outer,
+        outer,
     })
 }
 
@@ -30,16 +29,27 @@ pub fn env_bind(outer: Option<Env>, mbinds: MalVal, exprs: Vec<MalVal>) -> Resul
     let env = env_new(outer);
     match mbinds {
         List(binds, _) | Vector(binds, _) => {
-            for (i, b) in binds.iter().enumerate() {
-                match b {
-                    Sym(s) if s == "&" => {
-                        env_set(&env, binds[i + 1].clone(), list!(exprs[i..].to_vec()))?;
-                        break;
-                    }
-                    _ => {
-                        env_set(&env, b.clone(), exprs[i].clone())?;
-                    }
+            let variadic = binds.iter().position(|b| match b {
+                Sym(s) => s == "&",
+                _ => false,
+            });
+            let (min_args, max_args) = match variadic {
+                Some(i) if i + 2 == binds.len() => (i, VARIADIC),
+                Some(_) => {
+                    return Err(ErrString("'&' must be followed by one binding".to_string()))
                 }
+                None => (binds.len(), binds.len()),
+            };
+            check_arity("fn*", min_args, max_args, exprs.len())?;
+            for (i, b) in binds.iter().take(min_args).enumerate() {
+                env_set(&env, b.clone(), exprs[i].clone())?;
+            }
+            if variadic.is_some() {
+                env_set(
+                    &env,
+                    binds[min_args + 1].clone(),
+                    list!(exprs[min_args..].to_vec()),
+                )?;
             }
             Ok(env)
         }
@@ -55,6 +65,16 @@ pub fn env_find(env: &Env, key: &str) -> Option<Env> {
     }
 }
 
+/// Every symbol bound in `env` or the environments it is nested in.
+#[allow(dead_code)]
+pub fn env_symbols(env: &Env) -> Vec<String> {
+    let mut syms: Vec<String> = env.data.borrow().keys().cloned().collect();
+    if let Some(ref outer) = env.outer {
+        syms.extend(env_symbols(outer));
+    }
+    syms
+}
+
 pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
     match key {
         Sym(ref s) => match env_find(env, s) {
@@ -64,12 +84,21 @@ pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
                 .get(s)
                 .ok_or(ErrString(format!("'{}' not found", s)))?
                 .clone()),
-            _ => error(&format!("'{}' not found", s)),
+            _ => Err(ErrKind("unbound-symbol", format!("'{}' not found", s))),
         },
         _ => error("Env.get called with non-Str"),
     }
 }
 
+/// The symbol `env_get` failed to find, if that is all `e` is so far.
+#[allow(dead_code)]
+pub fn unbound_symbol(e: &MalErr) -> Option<&str> {
+    match e {
+        ErrKind("unbound-symbol", msg) => msg.strip_prefix('\'')?.strip_suffix("' not found"),
+        _ => None,
+    }
+}
+
 pub fn env_set(env: &Env, key: MalVal, val: MalVal) -> MalRet {
     match key {
         Sym(ref s) => {
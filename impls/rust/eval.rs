@@ -0,0 +1,535 @@
+use itertools::Itertools;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use crate::core::{concat, cons};
+use crate::env::{env_bind, env_find, env_get, env_new, env_set, unbound_symbol, Env};
+use crate::reader;
+use crate::shared::Rc;
+use crate::types::MalErr::{ErrKind, ErrString};
+use crate::types::MalVal::{
+    Bool, Exception, Func, Hash, Keyword, List, MalFunc, Nil, Set, Str, Sym, Vector,
+};
+use crate::types::{
+    check_arity, error, hash_map, hash_set, keyword, lazy_seq, source_location, type_error,
+    MalArgs, MalErr, MalRet, MalVal, MalVec,
+};
+
+// Evaluates a quasiquoted form directly, with the same cons and concat that
+// the usual rewrite into calls to them would run, but without building and
+// then evaluating that rewrite each time: macros like `or` expand through
+// quasiquote on every call.
+fn quasiquote(ast: &MalVal, env: &Env) -> MalRet {
+    match ast {
+        List(ref v, _) | Vector(ref v, _) if !v.is_empty() => match v[0] {
+            Sym(ref s) if s == "unquote" => {
+                check_arity("unquote", 1, 1, v.len() - 1)?;
+                eval(v[1].clone(), env.clone())
+            }
+            _ => {
+                let mut acc = list![];
+                for elt in v.iter().rev() {
+                    let spliced = match elt {
+                        List(ref v0, _) | Vector(ref v0, _) if v0.len() > 1 => match v0[0] {
+                            Sym(ref s) if s == "splice-unquote" => Some(&v0[1]),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    acc = match spliced {
+                        Some(form) => concat(vec![eval(form.clone(), env.clone())?, acc])?,
+                        None => cons(vec![quasiquote(elt, env)?, acc])?,
+                    };
+                }
+                Ok(acc)
+            }
+        },
+        _ => Ok(ast.clone()),
+    }
+}
+
+fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal, MalArgs)> {
+    match ast {
+        List(v, _) if !v.is_empty() => match v[0] {
+            Sym(ref s) => match env_find(env, s) {
+                Some(e) => match env_get(&e, &v[0]) {
+                    Ok(f @ MalFunc { is_macro: true, .. }) => {
+                        Some((f, v.iter().skip(1).cloned().collect()))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
+    let mut was_expanded = false;
+    while let Some((mf, args)) = is_macro_call(&ast, env) {
+        //println!("macroexpand 1: {:?}", ast);
+        ast = match mf.apply(args) {
+            Err(e) => return (false, Err(e)),
+            Ok(a) => a,
+        };
+        //println!("macroexpand 2: {:?}", ast);
+        was_expanded = true;
+    }
+    (was_expanded, Ok(ast))
+}
+
+fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
+    match ast {
+        Sym(_) => Ok(env_get(env, ast)?),
+        List(v, _) => {
+            let mut lst: MalArgs = vec![];
+            for a in v.iter() {
+                lst.push(eval(a.clone(), env.clone())?)
+            }
+            Ok(list!(lst))
+        }
+        Vector(v, _) => {
+            let mut lst: MalArgs = vec![];
+            for a in v.iter() {
+                lst.push(eval(a.clone(), env.clone())?)
+            }
+            Ok(vector!(lst))
+        }
+        Hash(hm, _) => {
+            let mut kvs: MalArgs = vec![];
+            for (k, v) in hm.iter() {
+                kvs.push(eval(k.clone(), env.clone())?);
+                kvs.push(eval(v.clone(), env.clone())?);
+            }
+            hash_map(kvs)
+        }
+        Set(s, _) => {
+            let mut lst: MalArgs = vec![];
+            for a in s.iter() {
+                lst.push(eval(a.clone(), env.clone())?)
+            }
+            hash_set(lst)
+        }
+        _ => Ok(ast.clone()),
+    }
+}
+
+// What an eval call was doing, to place errors that escape it in the
+// stack trace: the metadata of the innermost form with a source location,
+// and the mal function it tail-called into with the metadata of that call.
+struct EvalFrame {
+    loc: Option<Rc<MalVal>>,
+    in_fn: bool,
+    name: Option<Rc<str>>,
+    call_site: Option<Rc<MalVal>>,
+}
+
+fn location(meta: &Option<Rc<MalVal>>) -> Option<String> {
+    meta.as_ref().and_then(|m| source_location(m))
+}
+
+// Where the symbol an error says is unbound was read in the list located
+// by `meta`, if it was.
+fn symbol_location(e: &MalErr, meta: &Option<Rc<MalVal>>) -> Option<String> {
+    let name = unbound_symbol(e)?;
+    match meta.as_deref() {
+        Some(Hash(loc, symbols)) => match &**symbols {
+            Hash(symbols, _) => match symbols.get(&Sym(name.to_string())) {
+                Some(Vector(pos, _)) => {
+                    let mut loc = loc.clone();
+                    loc.insert(keyword("line"), pos[0].clone());
+                    loc.insert(keyword("column"), pos[1].clone());
+                    source_location(&Hash(loc, Rc::new(Nil)))
+                }
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn eval(ast: MalVal, env: Env) -> MalRet {
+    let mut frame = EvalFrame {
+        loc: None,
+        in_fn: false,
+        name: None,
+        call_site: None,
+    };
+    eval_tco(ast, env, &mut frame).map_err(|e| {
+        let at = symbol_location(&e, &frame.loc).or_else(|| location(&frame.loc));
+        let e = e.at(at);
+        if frame.in_fn {
+            let name = frame.name.as_deref().unwrap_or("fn*");
+            e.in_fn(name, location(&frame.call_site))
+        } else {
+            e
+        }
+    })
+}
+
+// Functions take the name they are first def!'d under, for stack traces.
+fn name_fn(val: MalVal, sym: &MalVal) -> MalVal {
+    match (val, sym) {
+        (
+            MalFunc {
+                eval,
+                ast,
+                env,
+                params,
+                name: None,
+                is_macro,
+                meta,
+            },
+            Sym(s),
+        ) => MalFunc {
+            eval,
+            ast,
+            env,
+            params,
+            name: Some(Rc::from(&s[..])),
+            is_macro,
+            meta,
+        },
+        (val, _) => val,
+    }
+}
+
+fn eval_tco(mut ast: MalVal, mut env: Env, frame: &mut EvalFrame) -> MalRet {
+    let ret: MalRet;
+
+    'tco: loop {
+        if let List(_, ref meta) = ast {
+            if let Hash(..) = **meta {
+                frame.loc = Some(meta.clone());
+            }
+        }
+        ret = match ast.clone() {
+            List(l, _) => {
+                if l.is_empty() {
+                    return Ok(ast);
+                }
+                match macroexpand(ast.clone(), &env) {
+                    (true, Ok(new_ast)) => {
+                        ast = new_ast;
+                        continue 'tco;
+                    }
+                    (_, Err(e)) => return Err(e),
+                    _ => (),
+                }
+
+                if l.is_empty() {
+                    return Ok(ast);
+                }
+                let a0 = &l[0];
+                match a0 {
+                    Sym(ref a0sym) if a0sym == "def!" => {
+                        check_arity("def!", 2, 2, l.len() - 1)?;
+                        let val = eval(l[2].clone(), env.clone())?;
+                        env_set(&env, l[1].clone(), name_fn(val, &l[1]))
+                    }
+                    Sym(ref a0sym) if a0sym == "let*" => {
+                        check_arity("let*", 2, 2, l.len() - 1)?;
+                        env = env_new(Some(env.clone()));
+                        let (a1, a2) = (l[1].clone(), l[2].clone());
+                        match a1 {
+                            List(ref binds, _) | Vector(ref binds, _) => {
+                                for (b, e) in binds.iter().tuples() {
+                                    match b {
+                                        Sym(_) => {
+                                            let _ = env_set(
+                                                &env,
+                                                b.clone(),
+                                                eval(e.clone(), env.clone())?,
+                                            );
+                                        }
+                                        _ => {
+                                            return error("let* with non-Sym binding");
+                                        }
+                                    }
+                                }
+                            }
+                            _ => {
+                                return error("let* with non-List bindings");
+                            }
+                        };
+                        ast = a2;
+                        continue 'tco;
+                    }
+                    Sym(ref a0sym) if a0sym == "quote" => {
+                        check_arity("quote", 1, 1, l.len() - 1)?;
+                        Ok(l[1].clone())
+                    }
+                    Sym(ref a0sym) if a0sym == "quasiquote" => {
+                        check_arity("quasiquote", 1, 1, l.len() - 1)?;
+                        quasiquote(&l[1], &env)
+                    }
+                    Sym(ref a0sym) if a0sym == "defmacro!" => {
+                        check_arity("defmacro!", 2, 2, l.len() - 1)?;
+                        let (a1, a2) = (l[1].clone(), l[2].clone());
+                        let r = eval(a2, env.clone())?;
+                        match r {
+                            MalFunc {
+                                eval,
+                                ast,
+                                env,
+                                params,
+                                ..
+                            } => Ok(env_set(
+                                &env,
+                                a1.clone(),
+                                name_fn(
+                                    MalFunc {
+                                        eval,
+                                        ast: ast.clone(),
+                                        env: env.clone(),
+                                        params: params.clone(),
+                                        name: None,
+                                        is_macro: true,
+                                        meta: Rc::new(Nil),
+                                    },
+                                    &a1,
+                                ),
+                            )?),
+                            _ => error("set_macro on non-function"),
+                        }
+                    }
+                    Sym(ref a0sym) if a0sym == "macroexpand" => {
+                        check_arity("macroexpand", 1, 1, l.len() - 1)?;
+                        match macroexpand(l[1].clone(), &env) {
+                            (_, Ok(new_ast)) => Ok(new_ast),
+                            (_, e) => return e,
+                        }
+                    }
+                    Sym(ref a0sym) if a0sym == "try*" => eval_try(&l, &env),
+                    Sym(ref a0sym) if a0sym == "do" => {
+                        for form in l.iter().skip(1).take(l.len().saturating_sub(2)) {
+                            eval(form.clone(), env.clone())?;
+                        }
+                        ast = match l.len() {
+                            1 => Nil,
+                            n => l[n - 1].clone(),
+                        };
+                        continue 'tco;
+                    }
+                    Sym(ref a0sym) if a0sym == "if" => {
+                        check_arity("if", 2, 3, l.len() - 1)?;
+                        let cond = eval(l[1].clone(), env.clone())?;
+                        match cond {
+                            Bool(false) | Nil if l.len() >= 4 => {
+                                ast = l[3].clone();
+                                continue 'tco;
+                            }
+                            Bool(false) | Nil => Ok(Nil),
+                            _ if l.len() >= 3 => {
+                                ast = l[2].clone();
+                                continue 'tco;
+                            }
+                            _ => Ok(Nil),
+                        }
+                    }
+                    Sym(ref a0sym) if a0sym == "fn*" => {
+                        check_arity("fn*", 2, 2, l.len() - 1)?;
+                        let (a1, a2) = (l[1].clone(), l[2].clone());
+                        Ok(MalFunc {
+                            eval,
+                            ast: Rc::new(a2),
+                            env,
+                            params: Rc::new(a1),
+                            name: None,
+                            is_macro: false,
+                            meta: Rc::new(Nil),
+                        })
+                    }
+                    Sym(ref a0sym) if a0sym == "lazy-seq" => {
+                        let body = match l.len() {
+                            1 => Nil,
+                            2 => l[1].clone(),
+                            _ => {
+                                let mut forms = l.skip(1);
+                                forms.push_front(Sym("do".to_string()));
+                                list!(forms)
+                            }
+                        };
+                        let thunk = MalFunc {
+                            eval,
+                            ast: Rc::new(body),
+                            env,
+                            params: Rc::new(list![]),
+                            name: None,
+                            is_macro: false,
+                            meta: Rc::new(Nil),
+                        };
+                        Ok(lazy_seq(move || thunk.apply(vec![])))
+                    }
+                    Sym(ref a0sym) if a0sym == "load-file" => {
+                        check_arity("load-file", 1, 1, l.len() - 1)?;
+                        let f = eval(l[1].clone(), env.clone())?;
+                        while let Some(ref e) = env.clone().outer {
+                            env = e.clone();
+                        }
+                        return load_file(&f, &env);
+                    }
+                    Sym(ref a0sym) if a0sym == "eval" => {
+                        check_arity("eval", 1, 1, l.len() - 1)?;
+                        ast = eval(l[1].clone(), env.clone())?;
+                        while let Some(ref e) = env.clone().outer {
+                            env = e.clone();
+                        }
+                        continue 'tco;
+                    }
+                    _ => {
+                        // the arguments are gathered straight into a Vec, rather
+                        // than into a mal list that apply would copy back out
+                        let f = eval(a0.clone(), env.clone())?;
+                        let mut args = Vec::with_capacity(l.len() - 1);
+                        for a in l.iter().skip(1) {
+                            args.push(eval(a.clone(), env.clone())?);
+                        }
+                        match &f {
+                            Func { name, .. } => f.apply(args).map_err(|e| e.in_fn(name, None)),
+                            Keyword(_) => f.apply(args),
+                            MalFunc {
+                                ast: mast,
+                                env: menv,
+                                params,
+                                name,
+                                ..
+                            } => {
+                                let a = &**mast;
+                                let p = &**params;
+                                env = env_bind(Some(menv.clone()), p.clone(), args)?;
+                                ast = a.clone();
+                                // a tail call replaces the running function but keeps
+                                // the site it was called from
+                                if !frame.in_fn {
+                                    frame.in_fn = true;
+                                    frame.call_site = frame.loc.clone();
+                                }
+                                frame.name = name.clone();
+                                frame.loc = None;
+                                continue 'tco;
+                            }
+                            _ => type_error("attempt to call non-function"),
+                        }
+                    }
+                }
+            }
+            _ => eval_ast(&ast, &env),
+        };
+
+        break;
+    } // end 'tco loop
+
+    ret
+}
+
+// Evaluates a file or other program one top-level form at a time, each read
+// only after the ones before it have run.
+pub fn load_file(f: &MalVal, env: &Env) -> MalRet {
+    let path = match f {
+        Str(path) => path,
+        _ => return type_error("load-file: expecting a file name"),
+    };
+    let file = File::open(path).map_err(|e| ErrString(format!("{}: {}", path, e)))?;
+    load(file, path, env)
+}
+
+pub fn load(src: impl Read, name: &str, env: &Env) -> MalRet {
+    for form in reader::read_forms_from(BufReader::new(src), Some(name.to_string())) {
+        eval(form?, env.clone())?;
+    }
+    Ok(Nil)
+}
+
+fn is_clause(form: &MalVal, head: &str) -> bool {
+    match form {
+        List(l, _) => matches!(l.front(), Some(Sym(s)) if s == head),
+        _ => false,
+    }
+}
+
+// (try* body (catch* [selector] sym handler)* (finally* form*)?)
+fn eval_try(l: &MalVec, env: &Env) -> MalRet {
+    if l.len() < 2 {
+        return error("try*: missing body");
+    }
+    let mut catches = vec![];
+    let mut finally = None;
+    for clause in l.iter().skip(2) {
+        if finally.is_some() {
+            return error("try*: finally* must be the last clause");
+        }
+        match clause {
+            List(c, _) if is_clause(clause, "catch*") => match c.len() {
+                3 | 4 if matches!(c[c.len() - 2], Sym(_)) => catches.push(c),
+                _ => return error("try*: expected (catch* [selector] symbol handler)"),
+            },
+            List(f, _) if is_clause(clause, "finally*") => finally = Some(f),
+            _ => return error("try*: expected a catch* or finally* clause"),
+        }
+    }
+    let res = match eval(l[1].clone(), env.clone()) {
+        Err(e) => catch(e, catches, env),
+        res => res,
+    };
+    if let Some(f) = finally {
+        for form in f.iter().skip(1) {
+            eval(form.clone(), env.clone())?;
+        }
+    }
+    res
+}
+
+// Runs the first catch* clause whose selector matches the error, or
+// rethrows it if none does. A clause without a selector binds the error
+// message, as in step 9; one with a selector binds an exception whose
+// data holds the error's `:type`.
+fn catch(e: MalErr, catches: Vec<&MalVec>, env: &Env) -> MalRet {
+    let exc = e.to_exception();
+    for c in catches {
+        let bound = if c.len() == 4 {
+            if !selects(&c[1], &exc, env)? {
+                continue;
+            }
+            exc
+        } else {
+            e.to_value()
+        };
+        let catch_env = env_bind(
+            Some(env.clone()),
+            list!(vec![c[c.len() - 2].clone()]),
+            vec![bound],
+        )?;
+        let trace: Vec<MalVal> = e.trace().iter().map(|f| Str(f.to_string())).collect();
+        env_set(&catch_env, Sym("*stack-trace*".to_string()), list!(trace))?;
+        return eval(c[c.len() - 1].clone(), catch_env);
+    }
+    Err(e)
+}
+
+// A keyword selects exceptions (or thrown maps) with that `:type`; any
+// other selector is evaluated to a predicate on the caught value.
+fn selects(selector: &MalVal, exc: &MalVal, env: &Env) -> Result<bool, MalErr> {
+    if let Keyword(_) = selector {
+        let data = match exc {
+            Exception(ex) => &ex.data,
+            _ => exc,
+        };
+        return Ok(match data {
+            Hash(hm, _) => hm.get(&keyword("type")) == Some(selector),
+            _ => false,
+        });
+    }
+    match eval(selector.clone(), env.clone())? {
+        f @ Func { .. } | f @ MalFunc { .. } => {
+            Ok(!matches!(f.apply(vec![exc.clone()])?, Nil | Bool(false)))
+        }
+        _ => Err(ErrKind(
+            "type-error",
+            "catch*: selector must be a keyword or a predicate".to_string(),
+        )),
+    }
+}
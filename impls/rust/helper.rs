@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use mal::env::{env_symbols, Env};
+use mal::reader::is_delimiter;
+use mal::types::interned_keywords;
+
+// Special forms are not bound in the environment but complete like symbols.
+const SPECIAL_FORMS: &[&str] = &[
+    "def!",
+    "defmacro!",
+    "do",
+    "eval",
+    "fn*",
+    "if",
+    "lazy-seq",
+    "let*",
+    "load-file",
+    "macroexpand",
+    "quasiquote",
+    "quote",
+    "try*",
+    "catch*",
+    "finally*",
+];
+
+/// Line editing support for the REPL: completion of symbols, keywords and
+/// `load-file` paths and matching-bracket highlighting.
+pub struct MalHelper {
+    env: Env,
+    files: FilenameCompleter,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl MalHelper {
+    pub fn new(env: Env) -> MalHelper {
+        MalHelper {
+            env,
+            files: FilenameCompleter::new(),
+            brackets: MatchingBracketHighlighter::new(),
+        }
+    }
+}
+
+// The index of the opening quote of a string left open at the end of
+// `s`, skipping over comments.
+fn unclosed_string(s: &str) -> Option<usize> {
+    let mut chars = s.char_indices();
+    let mut open = None;
+    while let Some((i, c)) = chars.next() {
+        match (open, c) {
+            (None, ';') => {
+                chars.by_ref().find(|&(_, c)| c == '\n');
+            }
+            (None, '"') => open = Some(i),
+            (Some(_), '"') => open = None,
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    open
+}
+
+impl Completer for MalHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        if let Some(quote) = unclosed_string(before) {
+            if before[..quote].trim_end().ends_with("(load-file") {
+                return self.files.complete_path(line, pos);
+            }
+            return Ok((pos, vec![]));
+        }
+        let mut start = before.rfind(is_delimiter).map_or(0, |i| i + 1);
+        start += before[start..]
+            .find(|c| !"@~^#".contains(c))
+            .unwrap_or(pos - start);
+        let word = &before[start..];
+        if word.is_empty() {
+            return Ok((pos, vec![]));
+        }
+        let mut names: Vec<String> = match word.strip_prefix(':') {
+            Some(prefix) => interned_keywords()
+                .iter()
+                .filter(|k| k.starts_with(prefix))
+                .map(|k| format!(":{}", k))
+                .collect(),
+            None => env_symbols(&self.env)
+                .into_iter()
+                .chain(SPECIAL_FORMS.iter().map(|s| s.to_string()))
+                .filter(|s| s.starts_with(word))
+                .collect(),
+        };
+        names.sort();
+        names.dedup();
+        let candidates = names
+            .into_iter()
+            .map(|s| Pair {
+                display: s.clone(),
+                replacement: s,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, forced: bool) -> bool {
+        self.brackets.highlight_char(line, pos, forced)
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+}
+
+// Unfinished forms are continued by the REPL loop, which reads each further
+// line after its own prompt.
+impl Validator for MalHelper {}
+
+impl Helper for MalHelper {}
@@ -0,0 +1,180 @@
+//! mal as a library, for embedding it as a configuration or scripting
+//! language in a Rust program.
+//!
+//! ```
+//! use mal::types::MalVal::Int;
+//! use mal::Interpreter;
+//!
+//! let mal = Interpreter::new();
+//! mal.define("limit", Int(10));
+//! mal.eval_str("(def! clamp (fn* [n] (if (> n limit) limit n)))").unwrap();
+//! assert_eq!(mal.call("clamp", vec![Int(42)]).unwrap(), Int(10));
+//! ```
+
+#[macro_use]
+extern crate lazy_static;
+extern crate fnv;
+extern crate im_rc;
+extern crate itertools;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+extern crate regex;
+extern crate rustyline;
+extern crate serde;
+
+use std::io::Read;
+
+#[macro_use]
+pub mod types;
+pub mod env;
+pub mod printer;
+pub mod reader;
+pub mod serialize;
+pub mod shared;
+#[macro_use]
+pub mod core;
+mod eval;
+
+use crate::env::{env_get, env_new, env_sets, Env};
+use crate::shared::Shareable;
+use crate::types::MalVal::{Nil, Str, Sym};
+use crate::types::{list, native_fn, MalArgs, MalRet, MalVal};
+
+// core.mal: defined using the language itself
+const PRELUDE: &[&str] = &[
+    "(def! *host-language* \"rust\")",
+    "(def! not (fn* (a) (if a false true)))",
+    // load-file is a special form, like eval, to reach the top-level
+    // environment; this makes it a function value as well.
+    "(def! load-file (fn* (f) (load-file f)))",
+    "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
+];
+
+/// A mal interpreter with the core library, in a top-level environment of
+/// its own.
+///
+/// With the `sync` feature, interpreters and values are `Send + Sync`, so
+/// interpreters can run on different threads and exchange values.
+///
+/// ```
+/// # #[cfg(feature = "sync")]
+/// # {
+/// use mal::Interpreter;
+/// use std::thread;
+///
+/// let worker = thread::spawn(|| {
+///     let mal = Interpreter::new();
+///     mal.eval_str("(fn* [x] (* x x))").unwrap()
+/// });
+/// let square = worker.join().unwrap();
+/// let mal = Interpreter::new();
+/// mal.define("square", square);
+/// assert_eq!(mal.eval_str("(square 7)").unwrap(), mal::types::MalVal::Int(49));
+/// # }
+/// ```
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let mal = Interpreter { env: env_new(None) };
+        mal.define_ns(core::ns());
+        mal.define("*ARGV*", list(vec![]));
+        for src in PRELUDE {
+            mal.eval_str(src).unwrap();
+        }
+        mal
+    }
+
+    /// The top-level environment.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// Evaluates `ast` at the top level.
+    pub fn eval(&self, ast: MalVal) -> MalRet {
+        eval::eval(ast, self.env.clone())
+    }
+
+    /// Reads and evaluates each form of `src` in turn, returning the value
+    /// of the last one, or nil if there are none.
+    pub fn eval_str(&self, src: &str) -> MalRet {
+        let mut val = Nil;
+        for form in reader::read_forms(src, None) {
+            val = self.eval(form?)?;
+        }
+        Ok(val)
+    }
+
+    /// Evaluates the file at `path`, as mal's `load-file` does.
+    pub fn load_file(&self, path: &str) -> MalRet {
+        eval::load_file(&Str(path.to_string()), &self.env)
+    }
+
+    /// Evaluates a program read from `src`, naming it `name` in the
+    /// locations of errors.
+    pub fn load(&self, src: impl Read, name: &str) -> MalRet {
+        eval::load(src, name, &self.env)
+    }
+
+    /// Calls the function bound to `name` with `args`.
+    pub fn call(&self, name: &str, args: MalArgs) -> MalRet {
+        env_get(&self.env, &Sym(name.to_string()))?.apply(args)
+    }
+
+    /// Binds `name` to `val` at the top level.
+    pub fn define(&self, name: &str, val: MalVal) {
+        env_sets(&self.env, name, val);
+    }
+
+    /// Binds `name` to a builtin backed by the closure `f`, which takes
+    /// between `min_args` and `max_args` arguments (`types::VARIADIC` for
+    /// any number).
+    ///
+    /// ```
+    /// use mal::types::MalVal::Int;
+    /// use mal::Interpreter;
+    /// use std::sync::atomic::{AtomicI64, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let mal = Interpreter::new();
+    /// let hits = Arc::new(AtomicI64::new(0));
+    /// let counter = hits.clone();
+    /// mal.define_fn("hit!", 0, 0, move |_| {
+    ///     Ok(Int(counter.fetch_add(1, Ordering::SeqCst) + 1))
+    /// });
+    /// mal.eval_str("(hit!) (hit!)").unwrap();
+    /// assert_eq!(hits.load(Ordering::SeqCst), 2);
+    /// assert_eq!(mal.eval_str("(pr-str hit!)").unwrap().pr_str(false), "#<native-fn hit!>");
+    /// ```
+    pub fn define_fn(
+        &self,
+        name: &str,
+        min_args: usize,
+        max_args: usize,
+        f: impl Fn(MalArgs) -> MalRet + Shareable + 'static,
+    ) {
+        self.define(name, native_fn(name, min_args, max_args, f));
+    }
+
+    /// Binds each of the builtins in `ns`, as `core::ns()` is bound.
+    pub fn define_ns(&self, ns: impl IntoIterator<Item = (&'static str, MalVal)>) {
+        for (k, v) in ns {
+            self.define(k, v);
+        }
+    }
+
+    /// The value bound to `name`, if it is bound.
+    pub fn get(&self, name: &str) -> Option<MalVal> {
+        env_get(&self.env, &Sym(name.to_string())).ok()
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
@@ -1,5 +1,19 @@
+use regex::Regex;
+
+use crate::types::format_error;
 use crate::types::MalVal;
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, List, MalFunc, Nil, Str, Sym, Vector};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Exception, Float, Func, Hash, HostObject, Int, Keyword, LazySeq, List,
+    MalFunc, Nil, Ratio, Set, Str, Sym, Vector,
+};
+
+// `alloc::vec::Vec<app::Db>` as `Vec<Db>`
+fn short_type_name(name: &str) -> String {
+    lazy_static! {
+        static ref PATH_RE: Regex = Regex::new(r"\w+::").unwrap();
+    }
+    PATH_RE.replace_all(name, "").into_owned()
+}
 
 fn escape_str(s: &str) -> String {
     s.chars()
@@ -20,42 +34,66 @@ impl MalVal {
             Bool(true) => String::from("true"),
             Bool(false) => String::from("false"),
             Int(i) => format!("{}", i),
-            //Float(f)    => format!("{}", f),
+            BigInt(n) if print_readably => format!("{}N", n),
+            BigInt(n) => format!("{}", n),
+            Ratio(r) => format!("{}/{}", r.numer(), r.denom()),
+            Float(f) if f.is_nan() => String::from("##NaN"),
+            Float(f) if f.is_infinite() && *f > 0.0 => String::from("##Inf"),
+            Float(f) if f.is_infinite() => String::from("##-Inf"),
+            Float(f) => format!("{:?}", f),
             Str(s) => {
-                if s.starts_with("\u{29e}") {
-                    format!(":{}", &s[2..])
-                } else if print_readably {
+                if print_readably {
                     format!("\"{}\"", escape_str(s))
                 } else {
                     s.clone()
                 }
             }
             Sym(s) => s.clone(),
-            List(l, _) => pr_seq(&**l, print_readably, "(", ")", " "),
-            Vector(l, _) => pr_seq(&**l, print_readably, "[", "]", " "),
+            Keyword(k) => format!(":{}", k),
+            List(l, _) => pr_seq(l, print_readably, "(", ")", " "),
+            Vector(l, _) => pr_seq(l, print_readably, "[", "]", " "),
             Hash(hm, _) => {
                 let l: Vec<MalVal> = hm
                     .iter()
-                    .flat_map(|(k, v)| vec![Str(k.to_string()), v.clone()])
+                    .flat_map(|(k, v)| vec![k.clone(), v.clone()])
                     .collect();
                 pr_seq(&l, print_readably, "{", "}", " ")
             }
-            Func(f, _) => format!("#<fn {:?}>", f),
+            Set(s, _) => pr_seq(s, print_readably, "#{", "}", " "),
+            LazySeq(_) => match self.realize() {
+                Ok(l) => pr_seq(&l, print_readably, "(", ")", " "),
+                Err(e) => format!("#<lazy-seq error: {}>", format_error(e)),
+            },
+            Func { name, .. } => format!("#<native-fn {}>", name),
             MalFunc {
                 ast: a, params: p, ..
             } => format!("(fn* {} {})", p.pr_str(true), a.pr_str(true)),
             Atom(a) => format!("(atom {})", a.borrow().pr_str(true)),
+            HostObject { type_name, .. } => format!("#<host {}>", short_type_name(type_name)),
+            Exception(ex) if print_readably => {
+                let cause = match ex.cause {
+                    Nil => String::new(),
+                    ref c => format!(" :cause {}", c.pr_str(true)),
+                };
+                format!(
+                    "#error {{:message {} :data {}{}}}",
+                    Str(ex.message.clone()).pr_str(true),
+                    ex.data.pr_str(true),
+                    cause
+                )
+            }
+            Exception(ex) => ex.message.clone(),
         }
     }
 }
 
-pub fn pr_seq(
-    seq: &Vec<MalVal>,
+pub fn pr_seq<'a>(
+    seq: impl IntoIterator<Item = &'a MalVal>,
     print_readably: bool,
     start: &str,
     end: &str,
     join: &str,
 ) -> String {
-    let strs: Vec<String> = seq.iter().map(|x| x.pr_str(print_readably)).collect();
+    let strs: Vec<String> = seq.into_iter().map(|x| x.pr_str(print_readably)).collect();
     format!("{}{}{}", start, strs.join(join), end)
 }
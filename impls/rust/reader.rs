@@ -1,113 +1,333 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
 use regex::{Captures, Regex};
-use std::rc::Rc;
+use std::io::BufRead;
 
-use crate::types::MalErr::ErrString;
-use crate::types::MalVal::{Bool, Int, List, Nil, Str, Sym, Vector};
-use crate::types::{error, hash_map, MalErr, MalRet, MalVal};
+use crate::shared::Rc;
+use crate::types::MalErr::{ErrKind, ErrString};
+use crate::types::MalVal::{Bool, Float, Hash, Int, List, Nil, Str, Sym, Vector};
+use crate::types::{
+    bigint, error, hash_map, hash_set, keyword, ratio, MalErr, MalMap, MalRet, MalVal,
+};
+
+// 1-based line and column
+type Pos = (usize, usize);
 
 #[derive(Debug, Clone)]
-struct Reader {
-    tokens: Vec<String>,
+struct Token {
+    text: String,
+    line: usize,
+    col: usize,
+}
+
+struct Reader<R> {
+    file: Option<String>,
+    lexer: Lexer<R>,
+    // the tokens lexed so far of the form being read
+    tokens: Vec<Token>,
     pos: usize,
+    // the symbols read and where, not yet claimed by an enclosing list
+    symbols: Vec<(String, Pos)>,
 }
 
-impl Reader {
-    fn next(&mut self) -> Result<String, MalErr> {
-        self.pos = self.pos + 1;
-        Ok(self
-            .tokens
-            .get(self.pos - 1)
-            .ok_or(ErrString("underflow".to_string()))?
-            .to_string())
+impl<R: BufRead> Reader<R> {
+    fn next(&mut self) -> Result<Token, MalErr> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Ok(token)
+    }
+    fn peek(&mut self) -> Result<Token, MalErr> {
+        while self.tokens.len() <= self.pos {
+            match self.lexer.token() {
+                Some(t) => self.tokens.push(t),
+                None => break,
+            }
+        }
+        match self.tokens.get(self.pos) {
+            Some(t) => Ok(t.clone()),
+            None => match self.lexer.end {
+                Some(End::Unterminated(pos)) => {
+                    Err(self.incomplete_at(pos, "expected '\"', got EOF"))
+                }
+                Some(End::Failed(ref e)) => Err(ErrString(match self.file {
+                    Some(ref f) => format!("{}: {}", f, e),
+                    None => e.clone(),
+                })),
+                _ => Err(self.incomplete_at((self.lexer.line, self.lexer.col), "unexpected EOF")),
+            },
+        }
     }
-    fn peek(&self) -> Result<String, MalErr> {
-        Ok(self
-            .tokens
-            .get(self.pos)
-            .ok_or(ErrString("underflow".to_string()))?
-            .to_string())
+    fn locate(&self, (line, col): Pos, msg: &str) -> String {
+        match self.file {
+            Some(ref f) => format!("{}:{}:{}: {}", f, line, col, msg),
+            None => format!("{}:{}: {}", line, col, msg),
+        }
+    }
+    fn error_at(&self, pos: Pos, msg: &str) -> MalErr {
+        ErrString(self.locate(pos, msg))
+    }
+    // Input that ends inside a form is incomplete rather than wrong, so the
+    // REPL can ask for more of it.
+    fn incomplete_at(&self, pos: Pos, msg: &str) -> MalErr {
+        ErrKind("incomplete-input", self.locate(pos, msg))
+    }
+    fn error(&self, token: &Token, msg: &str) -> MalErr {
+        self.error_at((token.line, token.col), msg)
     }
 }
 
-fn tokenize(str: &str) -> Vec<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(
-            r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###
-        )
-        .unwrap();
+pub fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "[]{}()'\"`,;".contains(c)
+}
+
+// How the input to a lexer ended.
+enum End {
+    Eof,
+    // in a string, with the position of its opening quote
+    Unterminated(Pos),
+    // with an error reading it
+    Failed(String),
+}
+
+// Splits the source into tokens as they are asked for, recording where each
+// one starts. Comments and commas are dropped. The source is read a line at
+// a time, and no further than the token asked for, so that each form of a
+// program read from a pipe or terminal can be evaluated as it arrives.
+struct Lexer<R> {
+    input: R,
+    // the line being lexed, and how much of it has been
+    chars: Vec<char>,
+    at: usize,
+    line: usize,
+    col: usize,
+    end: Option<End>,
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn new(input: R) -> Lexer<R> {
+        Lexer {
+            input,
+            chars: vec![],
+            at: 0,
+            line: 1,
+            col: 1,
+            end: None,
+        }
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        if self.at == self.chars.len() && self.end.is_none() {
+            let mut buf = String::new();
+            if let Err(e) = self.input.read_line(&mut buf) {
+                self.end = Some(End::Failed(e.to_string()));
+            }
+            self.chars = buf.chars().collect();
+            self.at = 0;
+        }
+        self.chars.get(self.at).copied()
+    }
+
+    fn next_char_if(&mut self, pred: impl Fn(char) -> bool) -> Option<char> {
+        let c = self.peek_char().filter(|&c| pred(c))?;
+        self.at += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
     }
 
-    let mut res = vec![];
-    for cap in RE.captures_iter(str) {
-        if cap[1].starts_with(";") {
-            continue;
+    fn next_char(&mut self) -> Option<char> {
+        self.next_char_if(|_| true)
+    }
+
+    // The next token, or None once the input has ended, leaving how it did
+    // in `end`.
+    fn token(&mut self) -> Option<Token> {
+        if self.end.is_some() {
+            return None;
+        }
+        loop {
+            let (line, col) = (self.line, self.col);
+            let c = match self.next_char() {
+                Some(c) => c,
+                None => return self.finish(End::Eof),
+            };
+            let mut text = c.to_string();
+            match c {
+                _ if c.is_whitespace() || c == ',' => continue,
+                // `#!` comments out a line, as in Clojure, so scripts can
+                // start with a shebang
+                _ if c == ';' || (c == '#' && self.peek_char() == Some('!')) => {
+                    while self.next_char_if(|c| c != '\n').is_some() {}
+                    continue;
+                }
+                '~' => text.extend(self.next_char_if(|c| c == '@')),
+                '#' if self.peek_char() == Some('{') => text.extend(self.next_char()),
+                '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '`' | '^' | '@' => {}
+                '"' => loop {
+                    match self.next_char() {
+                        Some('"') => {
+                            text.push('"');
+                            break;
+                        }
+                        Some('\\') => {
+                            text.push('\\');
+                            match self.next_char() {
+                                Some(c) => text.push(c),
+                                None => return self.finish(End::Unterminated((line, col))),
+                            }
+                        }
+                        Some(c) => text.push(c),
+                        None => return self.finish(End::Unterminated((line, col))),
+                    }
+                },
+                _ => {
+                    while let Some(c) = self.next_char_if(|c| !is_delimiter(c)) {
+                        text.push(c);
+                    }
+                }
+            }
+            return Some(Token { text, line, col });
         }
-        res.push(String::from(&cap[1]));
     }
-    res
+
+    // A read error takes precedence over where in the input it happened.
+    fn finish(&mut self, end: End) -> Option<Token> {
+        self.end.get_or_insert(end);
+        None
+    }
 }
 
 fn unescape_str(s: &str) -> String {
     lazy_static! {
         static ref RE: Regex = Regex::new(r#"\\(.)"#).unwrap();
     }
-    RE.replace_all(&s, |caps: &Captures| {
-        format!("{}", if &caps[1] == "n" { "\n" } else { &caps[1] })
+    RE.replace_all(s, |caps: &Captures| {
+        (if &caps[1] == "n" { "\n" } else { &caps[1] }).to_string()
     })
     .to_string()
 }
 
-fn read_atom(rdr: &mut Reader) -> MalRet {
+fn read_atom<R: BufRead>(rdr: &mut Reader<R>) -> MalRet {
     lazy_static! {
-        static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+$").unwrap();
-        static ref STR_RE: Regex = Regex::new(r#""(?:\\.|[^\\"])*""#).unwrap();
+        static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+N?$").unwrap();
+        static ref RATIO_RE: Regex = Regex::new(r"^(-?[0-9]+)/([0-9]+)$").unwrap();
+        static ref FLOAT_RE: Regex =
+            Regex::new(r"^-?[0-9]+(\.[0-9]*)?([eE][-+]?[0-9]+)?$").unwrap();
     }
-    let token = rdr.next()?;
+    let tok = rdr.next()?;
+    let token = &tok.text;
     match &token[..] {
         "nil" => Ok(Nil),
         "false" => Ok(Bool(false)),
         "true" => Ok(Bool(true)),
+        "##Inf" => Ok(Float(f64::INFINITY)),
+        "##-Inf" => Ok(Float(f64::NEG_INFINITY)),
+        "##NaN" => Ok(Float(f64::NAN)),
         _ => {
-            if INT_RE.is_match(&token) {
-                Ok(Int(token.parse().unwrap()))
-            } else if STR_RE.is_match(&token) {
+            if INT_RE.is_match(token) {
+                // Literals suffixed with N, or too large for an Int, are
+                // read as big integers
+                match token.strip_suffix('N') {
+                    Some(digits) => Ok(bigint(digits.parse().unwrap())),
+                    None => Ok(token
+                        .parse()
+                        .map(Int)
+                        .unwrap_or_else(|_| bigint(token.parse().unwrap()))),
+                }
+            } else if let Some(caps) = RATIO_RE.captures(token) {
+                let (numer, denom): (BigInt, BigInt) =
+                    (caps[1].parse().unwrap(), caps[2].parse().unwrap());
+                if denom.is_zero() {
+                    return Err(rdr.error(&tok, "Divide by zero"));
+                }
+                Ok(ratio(BigRational::new(numer, denom)))
+            } else if FLOAT_RE.is_match(token) {
+                Ok(Float(token.parse().unwrap()))
+            } else if token.starts_with('"') {
                 Ok(Str(unescape_str(&token[1..token.len() - 1])))
-            } else if token.starts_with("\"") {
-                error("expected '\"', got EOF")
-            } else if token.starts_with(":") {
-                Ok(Str(format!("\u{29e}{}", &token[1..])))
+            } else if let Some(k) = token.strip_prefix(':') {
+                Ok(keyword(k))
             } else {
+                rdr.symbols.push((token.to_string(), (tok.line, tok.col)));
                 Ok(Sym(token.to_string()))
             }
         }
     }
 }
 
-fn read_seq(rdr: &mut Reader, end: &str) -> MalRet {
+fn read_seq<R: BufRead>(rdr: &mut Reader<R>, end: &str) -> Result<Vec<MalVal>, MalErr> {
     let mut seq: Vec<MalVal> = vec![];
-    rdr.next()?;
+    let start = rdr.next()?;
     loop {
         let token = match rdr.peek() {
             Ok(t) => t,
-            Err(_) => return error(&format!("expected '{}', got EOF", end)),
+            Err(e) if !matches!(rdr.lexer.end, Some(End::Eof)) => return Err(e),
+            Err(_) => {
+                let msg = format!("unbalanced '{}', expected '{}', got EOF", start.text, end);
+                return Err(rdr.incomplete_at((start.line, start.col), &msg));
+            }
         };
-        if token == end {
+        if token.text == end {
             break;
         }
         seq.push(read_form(rdr)?)
     }
     let _ = rdr.next();
-    match end {
-        ")" => Ok(list!(seq)),
-        "]" => Ok(vector!(seq)),
-        "}" => hash_map(seq),
-        _ => error("read_seq unknown end value"),
+    Ok(seq)
+}
+
+// Lists remember where they were read as {:line :column :file} metadata,
+// so evaluation errors can point back at the source. The metadata of that
+// map in turn maps each symbol in the list, and in any vectors and maps in
+// it, to where it was first read there as [line column], to place an error
+// naming the symbol more precisely than at the list.
+fn read_list<R: BufRead>(rdr: &mut Reader<R>) -> MalRet {
+    let start = rdr.peek()?;
+    let mark = rdr.symbols.len();
+    let seq = read_seq(rdr, ")")?;
+    let mut symbols = MalMap::default();
+    for (name, (line, col)) in rdr.symbols.split_off(mark) {
+        symbols
+            .entry(Sym(name))
+            .or_insert_with(|| vector![Int(line as i64), Int(col as i64)]);
     }
+    let mut loc = vec![
+        keyword("line"),
+        Int(start.line as i64),
+        keyword("column"),
+        Int(start.col as i64),
+    ];
+    if let Some(ref f) = rdr.file {
+        loc.push(keyword("file"));
+        loc.push(Str(f.clone()));
+    }
+    let loc = hash_map(loc)?.with_meta(&Hash(symbols, Rc::new(Nil)))?;
+    list!(seq).with_meta(&loc)
 }
 
-fn read_form(rdr: &mut Reader) -> MalRet {
+// Runs a collection constructor, reporting its errors at the opening
+// delimiter.
+fn read_coll<R: BufRead>(
+    rdr: &mut Reader<R>,
+    end: &str,
+    make: fn(Vec<MalVal>) -> MalRet,
+) -> MalRet {
+    let start = rdr.peek()?;
+    let seq = read_seq(rdr, end)?;
+    make(seq).map_err(|e| match e {
+        ErrString(msg) => rdr.error(&start, &msg),
+        e => e,
+    })
+}
+
+fn read_form<R: BufRead>(rdr: &mut Reader<R>) -> MalRet {
     let token = rdr.peek()?;
-    match &token[..] {
+    match &token.text[..] {
         "'" => {
             let _ = rdr.next();
             Ok(list![Sym("quote".to_string()), read_form(rdr)?])
@@ -133,24 +353,75 @@ fn read_form(rdr: &mut Reader) -> MalRet {
             let _ = rdr.next();
             Ok(list![Sym("deref".to_string()), read_form(rdr)?])
         }
-        ")" => error("unexpected ')'"),
-        "(" => read_seq(rdr, ")"),
-        "]" => error("unexpected ']'"),
-        "[" => read_seq(rdr, "]"),
-        "}" => error("unexpected '}'"),
-        "{" => read_seq(rdr, "}"),
+        ")" | "]" | "}" => Err(rdr.error(&token, &format!("unexpected '{}'", token.text))),
+        "(" => read_list(rdr),
+        "[" => Ok(vector!(read_seq(rdr, "]")?)),
+        "{" => read_coll(rdr, "}", hash_map),
+        "#{" => read_coll(rdr, "}", hash_set),
         _ => read_atom(rdr),
     }
 }
 
+/// The top-level forms of a source, each read when it is asked for, so a
+/// read error is only reported once the forms before it are consumed, and
+/// a form is read from the source before any that follow it are.
+pub struct Forms<R> {
+    rdr: Reader<R>,
+    failed: bool,
+}
+
+impl<R: BufRead> Iterator for Forms<R> {
+    type Item = MalRet;
+
+    fn next(&mut self) -> Option<MalRet> {
+        let rdr = &mut self.rdr;
+        if self.failed || (rdr.peek().is_err() && matches!(rdr.lexer.end, Some(End::Eof))) {
+            return None;
+        }
+        let res = read_form(rdr);
+        // nothing after a read error can be trusted
+        self.failed = res.is_err();
+        rdr.tokens.drain(..rdr.pos);
+        rdr.pos = 0;
+        rdr.symbols.clear();
+        Some(res)
+    }
+}
+
+/// Reads successive forms from `str`, naming `file` in the location of
+/// any read error.
+pub fn read_forms(str: &str, file: Option<String>) -> Forms<&[u8]> {
+    read_forms_from(str.as_bytes(), file)
+}
+
+/// Like `read_forms`, for a source such as an open file.
+pub fn read_forms_from<R: BufRead>(src: R, file: Option<String>) -> Forms<R> {
+    Forms {
+        rdr: Reader {
+            file,
+            lexer: Lexer::new(src),
+            tokens: vec![],
+            pos: 0,
+            symbols: vec![],
+        },
+        failed: false,
+    }
+}
+
+/// Whether reading failed only because the input ended inside a form.
+#[allow(dead_code)]
+pub fn is_incomplete(e: &MalErr) -> bool {
+    matches!(e, ErrKind("incomplete-input", _))
+}
+
 pub fn read_str(str: String) -> MalRet {
-    let tokens = tokenize(&str);
-    //println!("tokens: {:?}", tokens);
-    if tokens.len() == 0 {
-        return error("no input");
-    }
-    read_form(&mut Reader {
-        pos: 0,
-        tokens: tokens,
-    })
+    read_str_from(str, None)
+}
+
+/// Reads the first form, naming `file` in the location of any read error.
+pub fn read_str_from(str: String, file: Option<String>) -> MalRet {
+    match read_forms(&str, file).next() {
+        Some(res) => res,
+        None => error("no input"),
+    }
 }
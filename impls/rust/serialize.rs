@@ -0,0 +1,868 @@
+//! Conversions between mal values and Rust data through serde.
+//!
+//! Structs become hash-maps keyed by keywords named after their fields,
+//! sequences become vectors and unit enum variants become keywords; other
+//! enum variants become a single-entry map from the variant keyword to its
+//! contents.
+//!
+//! ```
+//! extern crate serde;
+//! use mal::serialize::{from_value, to_value};
+//! use mal::types::MalVal::Int;
+//! use mal::Interpreter;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Server {
+//!     host: String,
+//!     port: u16,
+//! }
+//!
+//! let mal = Interpreter::new();
+//! let server = Server { host: "localhost".to_string(), port: 8080 };
+//! mal.define("server", to_value(&server).unwrap());
+//! assert_eq!(mal.eval_str("(:port server)").unwrap(), Int(8080));
+//!
+//! let moved: Server = from_value(mal.eval_str("(assoc server :port 9090)").unwrap()).unwrap();
+//! assert_eq!(moved.port, 9090);
+//!
+//! let bad = mal.eval_str(r#"[server {:host "b" :port "x"}]"#).unwrap();
+//! let err = from_value::<Vec<Server>>(bad).err().unwrap();
+//! assert_eq!(err.to_string(), "[1].port: invalid type: string \"x\", expected u16");
+//! ```
+
+use std::fmt;
+
+use num_traits::ToPrimitive;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::ser::{self, Serialize};
+
+use crate::shared::Rc;
+use crate::types::MalErr::ErrKind;
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Exception, Float, Func, Hash, HostObject, Int, Keyword, LazySeq, List,
+    MalFunc, Nil, Ratio, Set, Str, Sym, Vector,
+};
+use crate::types::{integer, keyword, vector, MalErr, MalMap, MalVal};
+
+/// Converts `value` to a mal value.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<MalVal, Error> {
+    value.serialize(Serializer)
+}
+
+/// Converts the mal value `val` to a `T`.
+pub fn from_value<T: DeserializeOwned>(val: MalVal) -> Result<T, Error> {
+    T::deserialize(val)
+}
+
+/// An error converting between mal values and Rust data, along with the
+/// path to the value at fault, such as `servers[1].port`.
+#[derive(Debug)]
+pub struct Error {
+    path: Vec<Segment>,
+    msg: String,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl Error {
+    // Records that the error is in `seg` of the enclosing value.
+    fn under(mut self, seg: Segment) -> Error {
+        self.path.insert(0, seg);
+        self
+    }
+
+    /// The path to the value at fault, empty when it is the outermost one.
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for seg in &self.path {
+            match seg {
+                Segment::Key(k) if path.is_empty() => path.push_str(k),
+                Segment::Key(k) => {
+                    path.push('.');
+                    path.push_str(k);
+                }
+                Segment::Index(i) => path.push_str(&format!("[{}]", i)),
+            }
+        }
+        path
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "{}: {}", self.path(), self.msg)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error {
+            path: vec![],
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error {
+            path: vec![],
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl From<Error> for MalErr {
+    fn from(e: Error) -> MalErr {
+        ErrKind("type-error", e.to_string())
+    }
+}
+
+// Keyword and string keys appear in paths by name.
+fn key_segment(k: &MalVal) -> Segment {
+    match k {
+        Keyword(k) => Segment::Key(k.to_string()),
+        Str(s) => Segment::Key(s.clone()),
+        _ => Segment::Key(k.pr_str(true)),
+    }
+}
+
+fn hash(map: MalMap) -> MalVal {
+    Hash(map, Rc::new(Nil))
+}
+
+// A map holding the contents of an enum variant under its name.
+fn variant(name: &'static str, val: MalVal) -> MalVal {
+    let mut map = MalMap::default();
+    map.insert(keyword(name), val);
+    hash(map)
+}
+
+/// Serializes Rust data as mal values.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = MalVal;
+    type Error = Error;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<MalVal, Error> {
+        Ok(Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<MalVal, Error> {
+        Ok(Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<MalVal, Error> {
+        Ok(Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<MalVal, Error> {
+        Ok(Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<MalVal, Error> {
+        Ok(Int(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<MalVal, Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<MalVal, Error> {
+        Ok(Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<MalVal, Error> {
+        Ok(Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<MalVal, Error> {
+        Ok(Int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<MalVal, Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<MalVal, Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<MalVal, Error> {
+        Ok(Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<MalVal, Error> {
+        Ok(Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<MalVal, Error> {
+        Ok(Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<MalVal, Error> {
+        Ok(Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<MalVal, Error> {
+        Ok(vector(v.iter().map(|&b| Int(b.into())).collect()))
+    }
+
+    fn serialize_none(self) -> Result<MalVal, Error> {
+        Ok(Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<MalVal, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<MalVal, Error> {
+        Ok(Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<MalVal, Error> {
+        Ok(Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<MalVal, Error> {
+        Ok(keyword(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<MalVal, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        value: &T,
+    ) -> Result<MalVal, Error> {
+        let val = to_value(value).map_err(|e| e.under(Segment::Key(name.to_string())))?;
+        Ok(variant(name, val))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, Error> {
+        Ok(SerializeVec {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, Error> {
+        Ok(SerializeVec {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, Error> {
+        Ok(SerializeMap {
+            map: MalMap::default(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, Error> {
+        Ok(SerializeMap {
+            map: MalMap::default(),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+/// Builds a vector, for sequences, tuples and tuple variants.
+pub struct SerializeVec {
+    items: Vec<MalVal>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeVec {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let i = self.items.len();
+        self.items
+            .push(to_value(value).map_err(|e| e.under(Segment::Index(i)))?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MalVal, Error> {
+        let vec = vector(self.items);
+        Ok(match self.variant {
+            Some(name) => variant(name, vec),
+            None => vec,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+/// Builds a hash-map, for maps, structs and struct variants.
+pub struct SerializeMap {
+    map: MalMap,
+    // the key of the entry whose value is next
+    key: Option<MalVal>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeMap {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: MalVal, value: &T) -> Result<(), Error> {
+        if !key.hashable() {
+            return Err(ser::Error::custom("key is not hashable"));
+        }
+        let val = to_value(value).map_err(|e| e.under(key_segment(&key)))?;
+        self.map.insert(key, val);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MalVal, Error> {
+        let map = hash(self.map);
+        Ok(match self.variant {
+            Some(name) => variant(name, map),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(to_value(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().unwrap_or(Nil);
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(keyword(key), value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = MalVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(keyword(key), value)
+    }
+
+    fn end(self) -> Result<MalVal, Error> {
+        self.finish()
+    }
+}
+
+// Keywords, strings and symbols all deserialize as strings, so that
+// struct fields can be looked up by keyword and enum variants named by one.
+impl<'de> de::Deserializer<'de> for MalVal {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Nil => visitor.visit_unit(),
+            Bool(b) => visitor.visit_bool(b),
+            Int(i) => visitor.visit_i64(i),
+            BigInt(n) => {
+                if let Some(u) = n.to_u64() {
+                    visitor.visit_u64(u)
+                } else if let Some(i) = n.to_i128() {
+                    visitor.visit_i128(i)
+                } else if let Some(u) = n.to_u128() {
+                    visitor.visit_u128(u)
+                } else {
+                    Err(de::Error::custom(format!("integer {} is out of range", n)))
+                }
+            }
+            Ratio(r) => match r.to_f64() {
+                Some(f) => visitor.visit_f64(f),
+                None => Err(de::Error::custom(format!("ratio {} is out of range", r))),
+            },
+            Float(f) => visitor.visit_f64(f),
+            Str(s) | Sym(s) => visitor.visit_string(s),
+            Keyword(k) => visitor.visit_str(&k),
+            List(v, _) | Vector(v, _) => visitor.visit_seq(SeqAccess::new(v.into_iter())),
+            Set(s, _) => visitor.visit_seq(SeqAccess::new(s.into_iter())),
+            LazySeq(_) => {
+                let v = self.realize().map_err(<Error as de::Error>::custom)?;
+                visitor.visit_seq(SeqAccess::new(v.into_iter()))
+            }
+            Hash(hm, _) => visitor.visit_map(MapAccess {
+                iter: hm.into_iter(),
+                value: None,
+            }),
+            Func { .. } | MalFunc { .. } => Err(de::Error::invalid_type(
+                Unexpected::Other("function"),
+                &visitor,
+            )),
+            Atom(_) => Err(de::Error::invalid_type(Unexpected::Other("atom"), &visitor)),
+            Exception(_) => Err(de::Error::invalid_type(
+                Unexpected::Other("exception"),
+                &visitor,
+            )),
+            HostObject { type_name, .. } => Err(de::Error::invalid_type(
+                Unexpected::Other(&format!("host {}", type_name)),
+                &visitor,
+            )),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Keyword(k) => visitor.visit_enum(k.to_string().into_deserializer()),
+            Str(s) => visitor.visit_enum(s.into_deserializer()),
+            Hash(hm, _) if hm.len() == 1 => {
+                let (variant, value) = hm.into_iter().next().unwrap();
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+            _ => Err(de::Error::invalid_type(
+                Unexpected::Other(&self.pr_str(true)),
+                &"a keyword or a map with a single entry",
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for MalVal {
+    type Deserializer = MalVal;
+
+    fn into_deserializer(self) -> MalVal {
+        self
+    }
+}
+
+struct SeqAccess<I> {
+    iter: I,
+    index: usize,
+}
+
+impl<I> SeqAccess<I> {
+    fn new(iter: I) -> SeqAccess<I> {
+        SeqAccess { iter, index: 0 }
+    }
+}
+
+impl<'de, I: Iterator<Item = MalVal>> de::SeqAccess<'de> for SeqAccess<I> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.iter.next() {
+            Some(v) => {
+                let i = self.index;
+                self.index += 1;
+                seed.deserialize(v)
+                    .map(Some)
+                    .map_err(|e| e.under(Segment::Index(i)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        match self.iter.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        }
+    }
+}
+
+struct MapAccess<I> {
+    iter: I,
+    // the value of the entry whose key was last read
+    value: Option<(Segment, MalVal)>,
+}
+
+impl<'de, I: Iterator<Item = (MalVal, MalVal)>> de::MapAccess<'de> for MapAccess<I> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((k, v)) => {
+                let seg = key_segment(&k);
+                self.value = Some((seg.clone(), v));
+                seed.deserialize(k).map(Some).map_err(|e| e.under(seg))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (seg, v) = self
+            .value
+            .take()
+            .unwrap_or((Segment::Key(String::new()), Nil));
+        seed.deserialize(v).map_err(|e| e.under(seg))
+    }
+}
+
+struct EnumAccess {
+    variant: MalVal,
+    value: MalVal,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantAccess), Error> {
+        let seg = key_segment(&self.variant);
+        let variant = seed.deserialize(self.variant)?;
+        Ok((
+            variant,
+            VariantAccess {
+                seg,
+                value: self.value,
+            },
+        ))
+    }
+}
+
+struct VariantAccess {
+    seg: Segment,
+    value: MalVal,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        let VariantAccess { seg, value } = self;
+        de::Deserialize::deserialize(value).map_err(|e: Error| e.under(seg))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        let VariantAccess { seg, value } = self;
+        seed.deserialize(value).map_err(|e| e.under(seg))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        let VariantAccess { seg, value } = self;
+        de::Deserializer::deserialize_seq(value, visitor).map_err(|e| e.under(seg))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let VariantAccess { seg, value } = self;
+        de::Deserializer::deserialize_map(value, visitor).map_err(|e| e.under(seg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Serialize};
+
+    use super::{from_value, to_value};
+    use crate::types::MalVal;
+    use crate::types::MalVal::{BigInt, Nil};
+    use crate::Interpreter;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect(i64, i64),
+        Named { name: String },
+    }
+
+    // A value that always fails to serialize.
+    struct Broken;
+
+    impl Serialize for Broken {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(S::Error::custom("broken"))
+        }
+    }
+
+    fn mal(src: &str) -> MalVal {
+        Interpreter::new().eval_str(src).unwrap()
+    }
+
+    fn printed<T: Serialize>(value: &T) -> String {
+        to_value(value).unwrap().pr_str(true)
+    }
+
+    fn error<T: for<'de> Deserialize<'de>>(src: &str) -> String {
+        from_value::<T>(mal(src)).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn enum_variants() {
+        assert_eq!(printed(&Shape::Empty), ":Empty");
+        assert_eq!(printed(&Shape::Circle(1.5)), "{:Circle 1.5}");
+        assert_eq!(printed(&Shape::Rect(2, 3)), "{:Rect [2 3]}");
+        let named = Shape::Named {
+            name: "a".to_string(),
+        };
+        assert_eq!(printed(&named), "{:Named {:name \"a\"}}");
+
+        for shape in [Shape::Empty, Shape::Circle(1.5), Shape::Rect(2, 3), named] {
+            let val = to_value(&shape).unwrap();
+            assert_eq!(from_value::<Shape>(val).unwrap(), shape);
+        }
+        assert_eq!(from_value::<Shape>(mal("\"Empty\"")).unwrap(), Shape::Empty);
+        assert_eq!(
+            from_value::<Shape>(mal("{:Rect (list 4 5)}")).unwrap(),
+            Shape::Rect(4, 5)
+        );
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(to_value(&None::<i64>).unwrap(), Nil);
+        assert_eq!(printed(&Some(3)), "3");
+        assert_eq!(from_value::<Option<i64>>(Nil).unwrap(), None);
+        assert_eq!(from_value::<Option<i64>>(mal("3")).unwrap(), Some(3));
+        assert_eq!(
+            from_value::<Vec<Option<String>>>(mal("[nil \"a\"]")).unwrap(),
+            vec![None, Some("a".to_string())]
+        );
+    }
+
+    #[test]
+    fn string_keyed_maps() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        let val = to_value(&map).unwrap();
+        let mal = Interpreter::new();
+        mal.define("m", val.clone());
+        assert_eq!(mal.eval_str("(get m \"b\")").unwrap().pr_str(true), "2");
+        assert_eq!(from_value::<HashMap<String, i64>>(val).unwrap(), map);
+    }
+
+    #[test]
+    fn big_integers() {
+        let big = to_value(&u64::MAX).unwrap();
+        assert!(matches!(big, BigInt(_)));
+        assert_eq!(big.pr_str(true), "18446744073709551615N");
+        assert_eq!(from_value::<u64>(big).unwrap(), u64::MAX);
+
+        let small = to_value(&i128::MIN).unwrap();
+        assert_eq!(small.pr_str(true), format!("{}N", i128::MIN));
+        assert_eq!(from_value::<i128>(small).unwrap(), i128::MIN);
+
+        // values that fit in an Int stay one
+        assert_eq!(printed(&7u64), "7");
+        assert_eq!(
+            error::<u64>("-1"),
+            "invalid value: integer `-1`, expected u64"
+        );
+    }
+
+    #[test]
+    fn sets_and_lazy_seqs() {
+        assert_eq!(
+            from_value::<BTreeSet<i64>>(mal("#{3 1 2}")).unwrap(),
+            vec![1, 2, 3].into_iter().collect()
+        );
+        assert_eq!(
+            from_value::<Vec<i64>>(mal("(take 4 (iterate (fn* [x] (+ x 1)) 0))")).unwrap(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            error::<Vec<i64>>("(cons 1 (lazy-seq (list (/ 1 0))))"),
+            "Divide by zero"
+        );
+    }
+
+    #[test]
+    fn error_paths() {
+        assert_eq!(
+            error::<HashMap<String, Vec<u8>>>("{\"a\" [1 300]}"),
+            "a[1]: invalid value: integer `300`, expected u8"
+        );
+        assert_eq!(
+            error::<Shape>("{:Rect [1 \"x\"]}"),
+            "Rect[1]: invalid type: string \"x\", expected i64"
+        );
+        assert_eq!(
+            error::<Vec<Shape>>("[:Empty {:Named {:name 1}}]"),
+            "[1].Named.name: invalid type: integer `1`, expected a string"
+        );
+        assert_eq!(
+            error::<Shape>(":Oval"),
+            "unknown variant `Oval`, expected one of `Empty`, `Circle`, `Rect`, `Named`"
+        );
+        assert_eq!(
+            error::<Shape>("[:Empty]"),
+            "invalid type: [:Empty], expected a keyword or a map with a single entry"
+        );
+
+        let mut map = HashMap::new();
+        map.insert("k", vec![Broken]);
+        assert_eq!(to_value(&map).err().unwrap().to_string(), "k[0]: broken");
+    }
+}
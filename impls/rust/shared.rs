@@ -0,0 +1,131 @@
+// Shared ownership and interior mutability for values and environments:
+// `Rc` and `RefCell` by default, or `Arc` and a `RwLock` under the same
+// names with the `sync` feature, which makes interpreters and their values
+// `Send + Sync`.
+
+#[cfg(not(feature = "sync"))]
+pub use std::cell::RefCell;
+#[cfg(not(feature = "sync"))]
+pub use std::rc::Rc;
+
+#[cfg(feature = "sync")]
+pub use self::lock::RefCell;
+#[cfg(feature = "sync")]
+pub use std::sync::Arc as Rc;
+
+/// Bounds closures and host objects held in values: `Send + Sync` with the
+/// `sync` feature, nothing otherwise.
+#[cfg(not(feature = "sync"))]
+pub trait Shareable {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> Shareable for T {}
+
+/// Bounds closures and host objects held in values: `Send + Sync` with the
+/// `sync` feature, nothing otherwise.
+#[cfg(feature = "sync")]
+pub trait Shareable: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + ?Sized> Shareable for T {}
+
+/// Replaces the value in `cell` with `f` of it, and returns the new value.
+/// The cell is not borrowed while `f` runs, so `f` may read it; with the
+/// `sync` feature, `f` is run again if another thread changes the value
+/// first, so that no update is lost.
+#[cfg(not(feature = "sync"))]
+pub fn update<T: Clone, E>(
+    cell: &RefCell<T>,
+    mut f: impl FnMut(T) -> Result<T, E>,
+) -> Result<T, E> {
+    let new = f(cell.borrow().clone())?;
+    *cell.borrow_mut() = new.clone();
+    Ok(new)
+}
+
+#[cfg(feature = "sync")]
+pub use self::lock::update;
+
+#[cfg(feature = "sync")]
+mod lock {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    /// A `RwLock` with the interface of a `RefCell`. Like a `RefCell`, a
+    /// panic while it is borrowed does not poison it.
+    #[derive(Debug, Default)]
+    pub struct RefCell<T> {
+        lock: RwLock<T>,
+        // counts mutable borrows, so update can tell whether the value
+        // changed while its function ran
+        writes: AtomicUsize,
+    }
+
+    impl<T> RefCell<T> {
+        pub fn new(val: T) -> RefCell<T> {
+            RefCell {
+                lock: RwLock::new(val),
+                writes: AtomicUsize::new(0),
+            }
+        }
+
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.lock.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            let guard = self.lock.write().unwrap_or_else(PoisonError::into_inner);
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            guard
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.lock.get_mut().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    /// Replaces the value in `cell` with `f` of it, and returns the new
+    /// value. The cell is not borrowed while `f` runs, so `f` may read it;
+    /// `f` is run again if another thread changes the value first, so that
+    /// no update is lost.
+    ///
+    /// ```
+    /// use mal::Interpreter;
+    /// use std::thread;
+    ///
+    /// let mal = Interpreter::new();
+    /// let counter = mal.eval_str("(atom 0)").unwrap();
+    /// let workers: Vec<_> = (0..4)
+    ///     .map(|_| {
+    ///         let counter = counter.clone();
+    ///         thread::spawn(move || {
+    ///             let mal = Interpreter::new();
+    ///             mal.define("counter", counter);
+    ///             mal.eval_str("(def! f (fn* [n] (if (> n 0) (do (swap! counter + 1) (f (- n 1))))))")?;
+    ///             mal.eval_str("(f 20000)")
+    ///         })
+    ///     })
+    ///     .collect();
+    /// for w in workers {
+    ///     w.join().unwrap().unwrap();
+    /// }
+    /// mal.define("counter", counter);
+    /// assert_eq!(mal.eval_str("@counter").unwrap(), mal::types::MalVal::Int(80000));
+    /// ```
+    pub fn update<T: Clone, E>(
+        cell: &RefCell<T>,
+        mut f: impl FnMut(T) -> Result<T, E>,
+    ) -> Result<T, E> {
+        loop {
+            let (old, writes) = {
+                let guard = cell.borrow();
+                (guard.clone(), cell.writes.load(Ordering::SeqCst))
+            };
+            let new = f(old)?;
+            let mut guard = cell.lock.write().unwrap_or_else(PoisonError::into_inner);
+            if cell.writes.load(Ordering::SeqCst) == writes {
+                cell.writes.fetch_add(1, Ordering::SeqCst);
+                *guard = new.clone();
+                return Ok(new);
+            }
+        }
+    }
+}
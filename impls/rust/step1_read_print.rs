@@ -1,13 +1,19 @@
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
+extern crate im_rc;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::DefaultEditor;
 
+mod shared;
 #[macro_use]
 #[allow(dead_code)]
 mod types;
@@ -20,7 +26,7 @@ mod env;
 
 fn main() {
     // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = DefaultEditor::new().unwrap();
     if rl.load_history(".mal-history").is_err() {
         eprintln!("No previous history.");
     }
@@ -29,9 +35,9 @@ fn main() {
         let readline = rl.readline("user> ");
         match readline {
             Ok(line) => {
-                rl.add_history_entry(&line);
+                let _ = rl.add_history_entry(&line);
                 rl.save_history(".mal-history").unwrap();
-                if line.len() > 0 {
+                if !line.is_empty() {
                     match reader::read_str(line) {
                         Ok(mv) => {
                             println!("{}", mv.pr_str(true));
@@ -1,23 +1,29 @@
-use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
+extern crate im_rc;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::DefaultEditor;
 
+mod shared;
 #[macro_use]
 #[allow(dead_code)]
 mod types;
-use crate::types::MalVal::{Hash, Int, List, Nil, Sym, Vector};
-use crate::types::{error, format_error, func, MalArgs, MalErr, MalRet, MalVal};
+use crate::types::MalVal::{Hash, Int, List, Nil, Set, Sym, Vector};
+use crate::types::{
+    error, format_error, func, hash_map, hash_set, MalArgs, MalErr, MalRet, MalVal,
+};
 mod env;
 mod printer;
 mod reader;
@@ -31,7 +37,7 @@ fn read(str: &str) -> MalRet {
 // eval
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
     match ast {
-        Sym(_) => Ok(env_get(&env, &ast)?),
+        Sym(_) => Ok(env_get(env, ast)?),
         List(v, _) => {
             let mut lst: MalArgs = vec![];
             for a in v.iter() {
@@ -47,11 +53,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut kvs: MalArgs = vec![];
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                kvs.push(eval(k.clone(), env.clone())?);
+                kvs.push(eval(v.clone(), env.clone())?);
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            hash_map(kvs)
+        }
+        Set(s, _) => {
+            let mut lst: MalArgs = vec![];
+            for a in s.iter() {
+                lst.push(eval(a.clone(), env.clone())?)
+            }
+            hash_set(lst)
         }
         _ => Ok(ast.clone()),
     }
@@ -60,7 +74,7 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
 fn eval(ast: MalVal, env: Env) -> MalRet {
     match ast.clone() {
         List(l, _) => {
-            if l.len() == 0 {
+            if l.is_empty() {
                 return Ok(ast);
             }
             let a0 = &l[0];
@@ -96,8 +110,8 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                 }
                 _ => match eval_ast(&ast, &env)? {
                     List(ref el, _) => {
-                        let ref f = el[0].clone();
-                        f.apply(el[1..].to_vec())
+                        let f = &el[0];
+                        f.apply(el.iter().skip(1).cloned().collect())
                     }
                     _ => error("expected a list"),
                 },
@@ -127,24 +141,40 @@ fn int_op(op: fn(i64, i64) -> i64, a: MalArgs) -> MalRet {
 
 fn main() {
     // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = DefaultEditor::new().unwrap();
     if rl.load_history(".mal-history").is_err() {
         eprintln!("No previous history.");
     }
 
     let repl_env = env_new(None);
-    env_sets(&repl_env, "+", func(|a: MalArgs| int_op(|i, j| i + j, a)));
-    env_sets(&repl_env, "-", func(|a: MalArgs| int_op(|i, j| i - j, a)));
-    env_sets(&repl_env, "*", func(|a: MalArgs| int_op(|i, j| i * j, a)));
-    env_sets(&repl_env, "/", func(|a: MalArgs| int_op(|i, j| i / j, a)));
+    env_sets(
+        &repl_env,
+        "+",
+        func("+", 2, 2, |a: MalArgs| int_op(|i, j| i + j, a)),
+    );
+    env_sets(
+        &repl_env,
+        "-",
+        func("-", 2, 2, |a: MalArgs| int_op(|i, j| i - j, a)),
+    );
+    env_sets(
+        &repl_env,
+        "*",
+        func("*", 2, 2, |a: MalArgs| int_op(|i, j| i * j, a)),
+    );
+    env_sets(
+        &repl_env,
+        "/",
+        func("/", 2, 2, |a: MalArgs| int_op(|i, j| i / j, a)),
+    );
 
     loop {
         let readline = rl.readline("user> ");
         match readline {
             Ok(line) => {
-                rl.add_history_entry(&line);
+                let _ = rl.add_history_entry(&line);
                 rl.save_history(".mal-history").unwrap();
-                if line.len() > 0 {
+                if !line.is_empty() {
                     match rep(&line, &repl_env) {
                         Ok(out) => println!("{}", out),
                         Err(e) => println!("Error: {}", format_error(e)),
@@ -1,22 +1,27 @@
-use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
+extern crate im_rc;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::DefaultEditor;
 
+mod shared;
 #[macro_use]
 mod types;
-use crate::types::MalVal::{Bool, Hash, List, MalFunc, Nil, Sym, Vector};
-use crate::types::{error, format_error, MalArgs, MalErr, MalRet, MalVal};
+use crate::shared::Rc;
+use crate::types::MalVal::{Bool, Hash, List, MalFunc, Nil, Set, Sym, Vector};
+use crate::types::{error, format_error, hash_map, hash_set, MalArgs, MalErr, MalRet, MalVal};
 mod env;
 mod printer;
 mod reader;
@@ -32,7 +37,7 @@ fn read(str: &str) -> MalRet {
 // eval
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
     match ast {
-        Sym(_) => Ok(env_get(&env, &ast)?),
+        Sym(_) => Ok(env_get(env, ast)?),
         List(v, _) => {
             let mut lst: MalArgs = vec![];
             for a in v.iter() {
@@ -48,11 +53,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut kvs: MalArgs = vec![];
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                kvs.push(eval(k.clone(), env.clone())?);
+                kvs.push(eval(v.clone(), env.clone())?);
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            hash_map(kvs)
+        }
+        Set(s, _) => {
+            let mut lst: MalArgs = vec![];
+            for a in s.iter() {
+                lst.push(eval(a.clone(), env.clone())?)
+            }
+            hash_set(lst)
         }
         _ => Ok(ast.clone()),
     }
@@ -61,7 +74,7 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
 fn eval(ast: MalVal, env: Env) -> MalRet {
     match ast.clone() {
         List(l, _) => {
-            if l.len() == 0 {
+            if l.is_empty() {
                 return Ok(ast);
             }
             let a0 = &l[0];
@@ -95,7 +108,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                     };
                     eval(a2, let_env)
                 }
-                Sym(ref a0sym) if a0sym == "do" => match eval_ast(&list!(l[1..].to_vec()), &env)? {
+                Sym(ref a0sym) if a0sym == "do" => match eval_ast(&list!(l.skip(1)), &env)? {
                     List(el, _) => Ok(el.last().unwrap_or(&Nil).clone()),
                     _ => error("invalid do form"),
                 },
@@ -111,18 +124,19 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                 Sym(ref a0sym) if a0sym == "fn*" => {
                     let (a1, a2) = (l[1].clone(), l[2].clone());
                     Ok(MalFunc {
-                        eval: eval,
+                        eval,
                         ast: Rc::new(a2),
-                        env: env,
+                        env,
                         params: Rc::new(a1),
+                        name: None,
                         is_macro: false,
                         meta: Rc::new(Nil),
                     })
                 }
                 _ => match eval_ast(&ast, &env)? {
                     List(ref el, _) => {
-                        let ref f = el[0].clone();
-                        f.apply(el[1..].to_vec())
+                        let f = &el[0];
+                        f.apply(el.iter().skip(1).cloned().collect())
                     }
                     _ => error("expected a list"),
                 },
@@ -145,7 +159,7 @@ fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
 
 fn main() {
     // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = DefaultEditor::new().unwrap();
     if rl.load_history(".mal-history").is_err() {
         eprintln!("No previous history.");
     }
@@ -164,9 +178,9 @@ fn main() {
         let readline = rl.readline("user> ");
         match readline {
             Ok(line) => {
-                rl.add_history_entry(&line);
+                let _ = rl.add_history_entry(&line);
                 rl.save_history(".mal-history").unwrap();
-                if line.len() > 0 {
+                if !line.is_empty() {
                     match rep(&line, &repl_env) {
                         Ok(out) => println!("{}", out),
                         Err(e) => println!("Error: {}", format_error(e)),
@@ -1,22 +1,27 @@
-use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
+extern crate im_rc;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::DefaultEditor;
 
+mod shared;
 #[macro_use]
 mod types;
-use crate::types::MalVal::{Bool, Func, Hash, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{error, format_error, MalArgs, MalErr, MalRet, MalVal};
+use crate::shared::Rc;
+use crate::types::MalVal::{Bool, Func, Hash, List, MalFunc, Nil, Set, Str, Sym, Vector};
+use crate::types::{error, format_error, hash_map, hash_set, MalArgs, MalErr, MalRet, MalVal};
 mod env;
 mod printer;
 mod reader;
@@ -32,27 +37,27 @@ fn read(str: &str) -> MalRet {
 // eval
 fn quasiquote(ast: &MalVal) -> MalVal {
     match ast {
-        List(ref v, _) | Vector(ref v, _) if v.len() > 0 => {
+        List(ref v, _) | Vector(ref v, _) if !v.is_empty() => {
             let a0 = &v[0];
             match a0 {
                 Sym(ref s) if s == "unquote" => v[1].clone(),
                 _ => match a0 {
-                    List(ref v0, _) | Vector(ref v0, _) if v0.len() > 0 => match v0[0] {
+                    List(ref v0, _) | Vector(ref v0, _) if !v0.is_empty() => match v0[0] {
                         Sym(ref s) if s == "splice-unquote" => list![
                             Sym("concat".to_string()),
                             v0[1].clone(),
-                            quasiquote(&list!(v[1..].to_vec()))
+                            quasiquote(&list!(v.skip(1)))
                         ],
                         _ => list![
                             Sym("cons".to_string()),
                             quasiquote(a0),
-                            quasiquote(&list!(v[1..].to_vec()))
+                            quasiquote(&list!(v.skip(1)))
                         ],
                     },
                     _ => list![
                         Sym("cons".to_string()),
                         quasiquote(a0),
-                        quasiquote(&list!(v[1..].to_vec()))
+                        quasiquote(&list!(v.skip(1)))
                     ],
                 },
             }
@@ -66,7 +71,9 @@ fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal, MalArgs)> {
         List(v, _) => match v[0] {
             Sym(ref s) => match env_find(env, s) {
                 Some(e) => match env_get(&e, &v[0]) {
-                    Ok(f @ MalFunc { is_macro: true, .. }) => Some((f, v[1..].to_vec())),
+                    Ok(f @ MalFunc { is_macro: true, .. }) => {
+                        Some((f, v.iter().skip(1).cloned().collect()))
+                    }
                     _ => None,
                 },
                 _ => None,
@@ -88,12 +95,12 @@ fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
         //println!("macroexpand 2: {:?}", ast);
         was_expanded = true;
     }
-    ((was_expanded, Ok(ast)))
+    (was_expanded, Ok(ast))
 }
 
 fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
     match ast {
-        Sym(_) => Ok(env_get(&env, &ast)?),
+        Sym(_) => Ok(env_get(env, ast)?),
         List(v, _) => {
             let mut lst: MalArgs = vec![];
             for a in v.iter() {
@@ -109,11 +116,19 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut kvs: MalArgs = vec![];
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                kvs.push(eval(k.clone(), env.clone())?);
+                kvs.push(eval(v.clone(), env.clone())?);
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            hash_map(kvs)
+        }
+        Set(s, _) => {
+            let mut lst: MalArgs = vec![];
+            for a in s.iter() {
+                lst.push(eval(a.clone(), env.clone())?)
+            }
+            hash_set(lst)
         }
         _ => Ok(ast.clone()),
     }
@@ -125,7 +140,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
     'tco: loop {
         ret = match ast.clone() {
             List(l, _) => {
-                if l.len() == 0 {
+                if l.is_empty() {
                     return Ok(ast);
                 }
                 match macroexpand(ast.clone(), &env) {
@@ -137,7 +152,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                     _ => (),
                 }
 
-                if l.len() == 0 {
+                if l.is_empty() {
                     return Ok(ast);
                 }
                 let a0 = &l[0];
@@ -191,10 +206,11 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                                 &env,
                                 a1.clone(),
                                 MalFunc {
-                                    eval: eval,
+                                    eval,
                                     ast: ast.clone(),
                                     env: env.clone(),
                                     params: params.clone(),
+                                    name: None,
                                     is_macro: true,
                                     meta: Rc::new(Nil),
                                 },
@@ -209,7 +225,7 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                         }
                     }
                     Sym(ref a0sym) if a0sym == "do" => {
-                        match eval_ast(&list!(l[1..l.len() - 1].to_vec()), &env)? {
+                        match eval_ast(&list!(l.clone().slice(1..l.len() - 1)), &env)? {
                             List(_, _) => {
                                 ast = l.last().unwrap_or(&Nil).clone();
                                 continue 'tco;
@@ -235,10 +251,11 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                     Sym(ref a0sym) if a0sym == "fn*" => {
                         let (a1, a2) = (l[1].clone(), l[2].clone());
                         Ok(MalFunc {
-                            eval: eval,
+                            eval,
                             ast: Rc::new(a2),
-                            env: env,
+                            env,
                             params: Rc::new(a1),
+                            name: None,
                             is_macro: false,
                             meta: Rc::new(Nil),
                         })
@@ -252,10 +269,10 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                     }
                     _ => match eval_ast(&ast, &env)? {
                         List(ref el, _) => {
-                            let ref f = el[0].clone();
-                            let args = el[1..].to_vec();
+                            let f = &el[0];
+                            let args = el.iter().skip(1).cloned().collect();
                             match f {
-                                Func(_, _) => f.apply(args),
+                                Func { .. } => f.apply(args),
                                 MalFunc {
                                     ast: mast,
                                     env: menv,
@@ -300,7 +317,7 @@ fn main() {
     let arg1 = args.nth(1);
 
     // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = DefaultEditor::new().unwrap();
     if rl.load_history(".mal-history").is_err() {
         eprintln!("No previous history.");
     }
@@ -310,7 +327,11 @@ fn main() {
     for (k, v) in core::ns() {
         env_sets(&repl_env, k, v);
     }
-    env_sets(&repl_env, "*ARGV*", list!(args.map(Str).collect()));
+    env_sets(
+        &repl_env,
+        "*ARGV*",
+        list!(args.map(Str).collect::<Vec<MalVal>>()),
+    );
 
     // core.mal: defined using the language itself
     let _ = rep("(def! not (fn* (a) (if a false true)))", &repl_env);
@@ -336,9 +357,9 @@ fn main() {
         let readline = rl.readline("user> ");
         match readline {
             Ok(line) => {
-                rl.add_history_entry(&line);
+                let _ = rl.add_history_entry(&line);
                 rl.save_history(".mal-history").unwrap();
-                if line.len() > 0 {
+                if !line.is_empty() {
                     match rep(&line, &repl_env) {
                         Ok(out) => println!("{}", out),
                         Err(e) => println!("Error: {}", format_error(e)),
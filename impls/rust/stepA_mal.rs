@@ -1,376 +1,237 @@
 #![allow(non_snake_case)]
 
-use std::rc::Rc;
-//use std::collections::HashMap;
-use fnv::FnvHashMap;
-use itertools::Itertools;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
 
-#[macro_use]
-extern crate lazy_static;
-extern crate fnv;
-extern crate itertools;
-extern crate regex;
+extern crate mal;
+use mal::reader;
+use mal::types::MalVal::{Int, Nil, Str};
+use mal::types::{error, format_error, list, type_error, MalArgs, MalErr, MalRet};
+use mal::Interpreter;
 
 extern crate rustyline;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
-
-#[macro_use]
-mod types;
-use crate::types::MalErr::{ErrMalVal, ErrString};
-use crate::types::MalVal::{Bool, Func, Hash, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{error, format_error, MalArgs, MalErr, MalRet, MalVal};
-mod env;
-mod printer;
-mod reader;
-use crate::env::{env_bind, env_find, env_get, env_new, env_set, env_sets, Env};
-#[macro_use]
-mod core;
+use rustyline::history::DefaultHistory;
+use rustyline::{Config, Editor};
+
+mod helper;
+use crate::helper::MalHelper;
+
+// Uncaught errors are shown with the mal stack they unwound through, each
+// run of a frame repeated by recursion shown once with its count.
+fn print_error(e: MalErr) {
+    let mut trace: Vec<(String, usize)> = vec![];
+    for frame in e.trace() {
+        let frame = frame.to_string();
+        match trace.last_mut() {
+            Some((last, n)) if *last == frame => *n += 1,
+            _ => trace.push((frame, 1)),
+        }
+    }
+    println!("Error: {}", format_error(e));
+    for (frame, n) in trace {
+        match n {
+            1 => println!("  at {}", frame),
+            n => println!("  at {} ({} times)", frame, n),
+        }
+    }
+}
 
-// read
-fn read(str: &str) -> MalRet {
-    reader::read_str(str.to_string())
+fn rep(str: &str, mal: &Interpreter) -> Result<String, MalErr> {
+    let ast = reader::read_str(str.to_string())?;
+    let exp = mal.eval(ast)?;
+    Ok(exp.pr_str(true))
 }
 
-// eval
-fn quasiquote(ast: &MalVal) -> MalVal {
-    match ast {
-        List(ref v, _) | Vector(ref v, _) if v.len() > 0 => {
-            let a0 = &v[0];
-            match a0 {
-                Sym(ref s) if s == "unquote" => v[1].clone(),
-                _ => match a0 {
-                    List(ref v0, _) | Vector(ref v0, _) if v0.len() > 0 => match v0[0] {
-                        Sym(ref s) if s == "splice-unquote" => list![
-                            Sym("concat".to_string()),
-                            v0[1].clone(),
-                            quasiquote(&list!(v[1..].to_vec()))
-                        ],
-                        _ => list![
-                            Sym("cons".to_string()),
-                            quasiquote(a0),
-                            quasiquote(&list!(v[1..].to_vec()))
-                        ],
-                    },
-                    _ => list![
-                        Sym("cons".to_string()),
-                        quasiquote(a0),
-                        quasiquote(&list!(v[1..].to_vec()))
-                    ],
-                },
-            }
+// Evaluates the forms given to -e, printing their values other than nil.
+fn eval_print(src: &str, mal: &Interpreter) -> MalRet {
+    for form in reader::read_forms(src, None) {
+        match mal.eval(form?)? {
+            Nil => {}
+            val => println!("{}", val.pr_str(true)),
         }
-        _ => list![Sym("quote".to_string()), ast.clone()],
     }
+    Ok(Nil)
 }
 
-fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal, MalArgs)> {
-    match ast {
-        List(v, _) => match v[0] {
-            Sym(ref s) => match env_find(env, s) {
-                Some(e) => match env_get(&e, &v[0]) {
-                    Ok(f @ MalFunc { is_macro: true, .. }) => Some((f, v[1..].to_vec())),
-                    _ => None,
-                },
-                _ => None,
-            },
-            _ => None,
-        },
-        _ => None,
-    }
+// Ends the process; it is bound here rather than in core::ns() so that an
+// embedded interpreter cannot take its host down.
+fn exit(a: MalArgs) -> MalRet {
+    let status = match a.first() {
+        None => 0,
+        // only the low byte of a status reaches the parent process
+        Some(Int(n @ 0..=255)) => *n as i32,
+        Some(Int(_)) => return error("exit: status out of range"),
+        _ => return type_error("exit: expecting an integer status"),
+    };
+    let _ = io::stdout().flush();
+    std::process::exit(status)
 }
 
-fn macroexpand(mut ast: MalVal, env: &Env) -> (bool, MalRet) {
-    let mut was_expanded = false;
-    while let Some((mf, args)) = is_macro_call(&ast, env) {
-        //println!("macroexpand 1: {:?}", ast);
-        ast = match mf.apply(args) {
-            Err(e) => return (false, Err(e)),
-            Ok(a) => a,
-        };
-        //println!("macroexpand 2: {:?}", ast);
-        was_expanded = true;
-    }
-    ((was_expanded, Ok(ast)))
+// What the command line asks main to run before exiting; the arguments
+// after it are bound to *ARGV*.
+enum Run {
+    Repl,
+    // -e expr
+    Eval(String),
+    Script(String),
+    // -, the program on stdin
+    Stdin,
+    // -i file, loaded before starting the REPL
+    Interactive(String),
 }
 
-fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
-    match ast {
-        Sym(_) => Ok(env_get(&env, &ast)?),
-        List(v, _) => {
-            let mut lst: MalArgs = vec![];
-            for a in v.iter() {
-                lst.push(eval(a.clone(), env.clone())?)
-            }
-            Ok(list!(lst))
-        }
-        Vector(v, _) => {
-            let mut lst: MalArgs = vec![];
-            for a in v.iter() {
-                lst.push(eval(a.clone(), env.clone())?)
-            }
-            Ok(vector!(lst))
-        }
-        Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
-            for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
-            }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
-        }
-        _ => Ok(ast.clone()),
-    }
+struct Cli {
+    run: Run,
+    // --history file
+    history: Option<String>,
 }
 
-fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
-    let ret: MalRet;
+const USAGE: &str =
+    "usage: stepA_mal [--history file] [-e expr | -i file | - | [--] file] [arg...]";
 
-    'tco: loop {
-        ret = match ast.clone() {
-            List(l, _) => {
-                if l.len() == 0 {
-                    return Ok(ast);
-                }
-                match macroexpand(ast.clone(), &env) {
-                    (true, Ok(new_ast)) => {
-                        ast = new_ast;
-                        continue 'tco;
-                    }
-                    (_, Err(e)) => return Err(e),
-                    _ => (),
-                }
-
-                if l.len() == 0 {
-                    return Ok(ast);
-                }
-                let a0 = &l[0];
-                match a0 {
-                    Sym(ref a0sym) if a0sym == "def!" => {
-                        env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?)
-                    }
-                    Sym(ref a0sym) if a0sym == "let*" => {
-                        env = env_new(Some(env.clone()));
-                        let (a1, a2) = (l[1].clone(), l[2].clone());
-                        match a1 {
-                            List(ref binds, _) | Vector(ref binds, _) => {
-                                for (b, e) in binds.iter().tuples() {
-                                    match b {
-                                        Sym(_) => {
-                                            let _ = env_set(
-                                                &env,
-                                                b.clone(),
-                                                eval(e.clone(), env.clone())?,
-                                            );
-                                        }
-                                        _ => {
-                                            return error("let* with non-Sym binding");
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {
-                                return error("let* with non-List bindings");
-                            }
-                        };
-                        ast = a2;
-                        continue 'tco;
-                    }
-                    Sym(ref a0sym) if a0sym == "quote" => Ok(l[1].clone()),
-                    Sym(ref a0sym) if a0sym == "quasiquote" => {
-                        ast = quasiquote(&l[1]);
-                        continue 'tco;
-                    }
-                    Sym(ref a0sym) if a0sym == "defmacro!" => {
-                        let (a1, a2) = (l[1].clone(), l[2].clone());
-                        let r = eval(a2, env.clone())?;
-                        match r {
-                            MalFunc {
-                                eval,
-                                ast,
-                                env,
-                                params,
-                                ..
-                            } => Ok(env_set(
-                                &env,
-                                a1.clone(),
-                                MalFunc {
-                                    eval: eval,
-                                    ast: ast.clone(),
-                                    env: env.clone(),
-                                    params: params.clone(),
-                                    is_macro: true,
-                                    meta: Rc::new(Nil),
-                                },
-                            )?),
-                            _ => error("set_macro on non-function"),
-                        }
-                    }
-                    Sym(ref a0sym) if a0sym == "macroexpand" => {
-                        match macroexpand(l[1].clone(), &env) {
-                            (_, Ok(new_ast)) => Ok(new_ast),
-                            (_, e) => return e,
-                        }
-                    }
-                    Sym(ref a0sym) if a0sym == "try*" => match eval(l[1].clone(), env.clone()) {
-                        Err(ref e) if l.len() >= 3 => {
-                            let exc = match e {
-                                ErrMalVal(mv) => mv.clone(),
-                                ErrString(s) => Str(s.to_string()),
-                            };
-                            match l[2].clone() {
-                                List(c, _) => {
-                                    let catch_env = env_bind(
-                                        Some(env.clone()),
-                                        list!(vec![c[1].clone()]),
-                                        vec![exc],
-                                    )?;
-                                    eval(c[2].clone(), catch_env)
-                                }
-                                _ => error("invalid catch block"),
-                            }
-                        }
-                        res => res,
-                    },
-                    Sym(ref a0sym) if a0sym == "do" => {
-                        match eval_ast(&list!(l[1..l.len() - 1].to_vec()), &env)? {
-                            List(_, _) => {
-                                ast = l.last().unwrap_or(&Nil).clone();
-                                continue 'tco;
-                            }
-                            _ => error("invalid do form"),
-                        }
-                    }
-                    Sym(ref a0sym) if a0sym == "if" => {
-                        let cond = eval(l[1].clone(), env.clone())?;
-                        match cond {
-                            Bool(false) | Nil if l.len() >= 4 => {
-                                ast = l[3].clone();
-                                continue 'tco;
-                            }
-                            Bool(false) | Nil => Ok(Nil),
-                            _ if l.len() >= 3 => {
-                                ast = l[2].clone();
-                                continue 'tco;
-                            }
-                            _ => Ok(Nil),
-                        }
-                    }
-                    Sym(ref a0sym) if a0sym == "fn*" => {
-                        let (a1, a2) = (l[1].clone(), l[2].clone());
-                        Ok(MalFunc {
-                            eval: eval,
-                            ast: Rc::new(a2),
-                            env: env,
-                            params: Rc::new(a1),
-                            is_macro: false,
-                            meta: Rc::new(Nil),
-                        })
-                    }
-                    Sym(ref a0sym) if a0sym == "eval" => {
-                        ast = eval(l[1].clone(), env.clone())?;
-                        while let Some(ref e) = env.clone().outer {
-                            env = e.clone();
-                        }
-                        continue 'tco;
-                    }
-                    _ => match eval_ast(&ast, &env)? {
-                        List(ref el, _) => {
-                            let ref f = el[0].clone();
-                            let args = el[1..].to_vec();
-                            match f {
-                                Func(_, _) => f.apply(args),
-                                MalFunc {
-                                    ast: mast,
-                                    env: menv,
-                                    params,
-                                    ..
-                                } => {
-                                    let a = &**mast;
-                                    let p = &**params;
-                                    env = env_bind(Some(menv.clone()), p.clone(), args)?;
-                                    ast = a.clone();
-                                    continue 'tco;
-                                }
-                                _ => error("attempt to call non-function"),
-                            }
-                        }
-                        _ => error("expected a list"),
-                    },
+fn parse_args(args: &mut impl Iterator<Item = String>) -> Option<Cli> {
+    let mut history = None;
+    loop {
+        let run = match args.next() {
+            None => Run::Repl,
+            Some(arg) => match &arg[..] {
+                "--history" => {
+                    history = Some(args.next()?);
+                    continue;
                 }
-            }
-            _ => eval_ast(&ast, &env),
+                "-e" => Run::Eval(args.next()?),
+                "-i" => Run::Interactive(args.next()?),
+                "-" => Run::Stdin,
+                "--" => Run::Script(args.next()?),
+                _ if arg.starts_with('-') => return None,
+                _ => Run::Script(arg),
+            },
         };
+        return Some(Cli { run, history });
+    }
+}
 
-        break;
-    } // end 'tco loop
-
-    ret
+// The REPL history file is the --history flag, else $MAL_HISTORY, else
+// mal/history under $XDG_STATE_HOME or ~/.local/state. An empty name turns
+// history off.
+fn history_path(flag: Option<String>) -> Option<PathBuf> {
+    let path = match flag.or_else(|| std::env::var("MAL_HISTORY").ok()) {
+        Some(ref p) if p.is_empty() => return None,
+        Some(p) => PathBuf::from(p),
+        None => {
+            let state = match std::env::var_os("XDG_STATE_HOME") {
+                Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+                _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+            };
+            state.join("mal").join("history")
+        }
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    Some(path)
 }
 
-// print
-fn print(ast: &MalVal) -> String {
-    ast.pr_str(true)
+// The number of history entries kept, from $MAL_HISTORY_SIZE.
+fn history_size() -> usize {
+    std::env::var("MAL_HISTORY_SIZE")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1000)
 }
 
-fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
-    let ast = read(str)?;
-    let exp = eval(ast, env.clone())?;
-    Ok(print(&exp))
+// ~/.malrc, if there is one, is loaded into the REPL environment.
+fn load_rc(mal: &Interpreter) {
+    let rc = match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".malrc"),
+        None => return,
+    };
+    if rc.is_file() {
+        if let Err(e) = mal.load_file(&rc.to_string_lossy()) {
+            print_error(e);
+        }
+    }
 }
 
 fn main() {
-    let mut args = std::env::args();
-    let arg1 = args.nth(1);
+    let mut args = std::env::args().skip(1);
+    let Cli { run, history } = match parse_args(&mut args) {
+        Some(cli) => cli,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
-    if rl.load_history(".mal-history").is_err() {
-        eprintln!("No previous history.");
-    }
+    let mal = Interpreter::new();
+    mal.define("*ARGV*", list(args.map(Str).collect()));
+    mal.define_fn("exit", 0, 1, exit);
 
-    // core.rs: defined using rust
-    let repl_env = env_new(None);
-    for (k, v) in core::ns() {
-        env_sets(&repl_env, k, v);
+    if let Run::Repl | Run::Interactive(_) = run {
+        load_rc(&mal);
+    }
+    let res = match run {
+        Run::Repl => Ok(Nil),
+        Run::Eval(ref src) => eval_print(src, &mal),
+        Run::Script(ref f) | Run::Interactive(ref f) => mal.load_file(f),
+        Run::Stdin => mal.load(io::stdin(), "<stdin>"),
+    };
+    if let Err(e) = res {
+        print_error(e);
+        std::process::exit(1);
+    }
+    if let Run::Eval(_) | Run::Script(_) | Run::Stdin = run {
+        return;
     }
-    env_sets(&repl_env, "*ARGV*", list!(args.map(Str).collect()));
-
-    // core.mal: defined using the language itself
-    let _ = rep("(def! *host-language* \"rust\")", &repl_env);
-    let _ = rep("(def! not (fn* (a) (if a false true)))", &repl_env);
-    let _ = rep(
-        "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\nnil)\")))))",
-        &repl_env,
-    );
-    let _ = rep("(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))", &repl_env);
 
-    // Invoked with arguments
-    if let Some(f) = arg1 {
-        match rep(&format!("(load-file \"{}\")", f), &repl_env) {
-            Ok(_) => std::process::exit(0),
-            Err(e) => {
-                println!("Error: {}", format_error(e));
-                std::process::exit(1);
-            }
-        }
+    let config = Config::builder()
+        .max_history_size(history_size())
+        .and_then(|c| c.history_ignore_dups(true))
+        .unwrap()
+        .build();
+    let mut rl = Editor::<MalHelper, DefaultHistory>::with_config(config).unwrap();
+    let history = history_path(history);
+    if let Some(ref path) = history {
+        // there is no history yet on a first run
+        let _ = rl.load_history(path);
     }
+    rl.set_helper(Some(MalHelper::new(mal.env().clone())));
 
     // main repl loop
-    let _ = rep("(println (str \"Mal [\" *host-language* \"]\"))", &repl_env);
+    let _ = rep("(println (str \"Mal [\" *host-language* \"]\"))", &mal);
+    // lines of a form that is not finished yet
+    let mut input = String::new();
     loop {
-        let readline = rl.readline("user> ");
+        let readline = rl.readline(if input.is_empty() { "user> " } else { "  ..> " });
         match readline {
             Ok(line) => {
-                rl.add_history_entry(&line);
-                rl.save_history(".mal-history").unwrap();
-                if line.len() > 0 {
-                    match rep(&line, &repl_env) {
-                        Ok(out) => println!("{}", out),
-                        Err(e) => println!("Error: {}", format_error(e)),
-                    }
+                if input.is_empty() && line.is_empty() {
+                    continue;
                 }
+                if !input.is_empty() {
+                    input.push('\n');
+                }
+                input.push_str(&line);
+                if matches!(reader::read_str(input.clone()), Err(ref e) if reader::is_incomplete(e))
+                {
+                    continue;
+                }
+                let _ = rl.add_history_entry(&input);
+                if let Some(ref path) = history {
+                    let _ = rl.append_history(path);
+                }
+                match rep(&input, &mal) {
+                    Ok(out) => println!("{}", out),
+                    Err(e) => print_error(e),
+                }
+                input.clear();
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
             }
-            Err(ReadlineError::Interrupted) => continue,
             Err(ReadlineError::Eof) => break,
             Err(err) => {
                 println!("Error: {:?}", err);
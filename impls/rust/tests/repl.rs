@@ -0,0 +1,35 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs the stepA REPL on `input`, without a ~/.malrc or history file.
+fn repl(input: &str) -> String {
+    let home = std::env::temp_dir().join("mal-repl-test");
+    std::fs::create_dir_all(&home).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stepA_mal"))
+        .env("HOME", &home)
+        .env("MAL_HISTORY", "")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn string_spanning_lines() {
+    let out = repl("(def! s \"a\nb\")\n(= s \"a\\nb\")\n");
+    assert!(out.ends_with("\"a\\nb\"\ntrue\n"), "{:?}", out);
+}
+
+#[test]
+fn form_spanning_lines() {
+    let out = repl("(+ 1\n\n  2)\n");
+    assert!(out.ends_with("\n3\n"), "{:?}", out);
+}
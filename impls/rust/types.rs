@@ -1,64 +1,224 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::any::Any;
+use std::fmt;
+use std::hash::Hasher;
+use std::iter;
+use std::mem;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
+use fnv::{FnvBuildHasher, FnvHashSet, FnvHasher};
 use itertools::Itertools;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
 
 use crate::env::{env_bind, Env};
-use crate::types::MalErr::{ErrMalVal, ErrString};
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, List, MalFunc, Nil, Str, Sym, Vector};
+use crate::shared::{self, Rc, RefCell, Shareable};
+use crate::types::MalErr::{ErrKind, ErrMalVal, ErrString, ErrTrace};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Exception, Float, Func, Hash, HostObject, Int, Keyword, LazySeq, List,
+    MalFunc, Nil, Ratio, Set, Str, Sym, Vector,
+};
 
 #[derive(Debug, Clone)]
 pub enum MalVal {
     Nil,
     Bool(bool),
     Int(i64),
-    //Float(f64),
+    BigInt(Rc<num_bigint::BigInt>),
+    Ratio(Rc<BigRational>),
+    Float(f64),
     Str(String),
     Sym(String),
-    List(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Hash(Rc<FnvHashMap<String, MalVal>>, Rc<MalVal>),
-    Func(fn(MalArgs) -> MalRet, Rc<MalVal>),
+    Keyword(Rc<str>),
+    List(MalVec, Rc<MalVal>),
+    Vector(MalVec, Rc<MalVal>),
+    Hash(MalMap, Rc<MalVal>),
+    Set(MalSet, Rc<MalVal>),
+    LazySeq(Rc<Lazy>),
+    Func {
+        name: Rc<str>,
+        f: NativeFn,
+        min_args: usize,
+        max_args: usize,
+        meta: Rc<MalVal>,
+    },
     MalFunc {
         eval: fn(ast: MalVal, env: Env) -> MalRet,
         ast: Rc<MalVal>,
         env: Env,
         params: Rc<MalVal>,
+        // the name a function was first def!'d under, for stack traces
+        name: Option<Rc<str>>,
         is_macro: bool,
         meta: Rc<MalVal>,
     },
     Atom(Rc<RefCell<MalVal>>),
+    Exception(Rc<ExInfo>),
+    // a Rust value handed to mal by a host program, opaque to mal code
+    #[allow(dead_code)]
+    HostObject {
+        obj: Rc<HostAny>,
+        type_name: &'static str,
+        meta: Rc<MalVal>,
+    },
 }
 
+/// An exception built by `ex-info`, or an interpreter error caught by
+/// `catch*`.
 #[derive(Debug)]
+pub struct ExInfo {
+    pub message: String,
+    // always a hash-map
+    pub data: MalVal,
+    // the exception this one wraps, or Nil
+    pub cause: MalVal,
+}
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum MalErr {
     ErrString(String),
+    // an interpreter error whose exception has `:type` set to the keyword
+    ErrKind(&'static str, String),
     ErrMalVal(MalVal),
+    // an error with the mal call stack it unwound through, innermost first
+    ErrTrace(Box<MalErr>, Vec<Frame>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    // the function being run, or None for a location not yet attributed
+    // to a function (at the top level, the form typed or loaded)
+    pub name: Option<Rc<str>>,
+    // `file:line:column` of the innermost located form, if any
+    pub at: Option<String>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.name, &self.at) {
+            (Some(name), Some(at)) => write!(f, "{} ({})", name, at),
+            (Some(name), None) => write!(f, "{}", name),
+            (None, Some(at)) => write!(f, "{}", at),
+            (None, None) => write!(f, "?"),
+        }
+    }
 }
 
 pub type MalArgs = Vec<MalVal>;
 pub type MalRet = Result<MalVal, MalErr>;
 
+// The Rust side of a builtin. It is a closure rather than a fn pointer so
+// that builtins registered by a host program can capture state.
+#[derive(Clone)]
+pub struct NativeFn(pub Rc<Builtin>);
+
+#[cfg(not(feature = "sync"))]
+pub type Builtin = dyn Fn(MalArgs) -> MalRet;
+#[cfg(feature = "sync")]
+pub type Builtin = dyn Fn(MalArgs) -> MalRet + Send + Sync;
+
+#[cfg(not(feature = "sync"))]
+type Thunk = dyn Fn() -> MalRet;
+#[cfg(feature = "sync")]
+type Thunk = dyn Fn() -> MalRet + Send + Sync;
+
+#[cfg(not(feature = "sync"))]
+pub type HostAny = dyn Any;
+#[cfg(feature = "sync")]
+pub type HostAny = dyn Any + Send + Sync;
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFn({:p})", Rc::as_ptr(&self.0))
+    }
+}
+
+// Persistent collections: clones share structure and updates are
+// O(log n), so conj/assoc on a large collection does not copy it.
+#[cfg(not(feature = "sync"))]
+pub type MalVec = im_rc::Vector<MalVal>;
+#[cfg(not(feature = "sync"))]
+pub type MalMap = im_rc::HashMap<MalVal, MalVal, FnvBuildHasher>;
+#[cfg(not(feature = "sync"))]
+pub type MalSet = im_rc::HashSet<MalVal, FnvBuildHasher>;
+
+#[cfg(feature = "sync")]
+pub type MalVec = im::Vector<MalVal>;
+#[cfg(feature = "sync")]
+pub type MalMap = im::HashMap<MalVal, MalVal, FnvBuildHasher>;
+#[cfg(feature = "sync")]
+pub type MalSet = im::HashSet<MalVal, FnvBuildHasher>;
+
+// A lazy sequence is realized one cell at a time: forcing a pending cell
+// runs its thunk once and caches the resulting first element and rest.
+pub struct Lazy {
+    state: RefCell<LazyState>,
+}
+
+enum LazyState {
+    Pending(Rc<Thunk>),
+    Realized(Option<(MalVal, MalVal)>),
+}
+
+impl Lazy {
+    fn force(&self) -> Result<Option<(MalVal, MalVal)>, MalErr> {
+        let thunk = match *self.state.borrow() {
+            LazyState::Realized(ref step) => return Ok(step.clone()),
+            LazyState::Pending(ref thunk) => thunk.clone(),
+        };
+        let step = thunk()?.uncons()?;
+        *self.state.borrow_mut() = LazyState::Realized(step.clone());
+        Ok(step)
+    }
+}
+
+impl fmt::Debug for Lazy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.state.borrow() {
+            LazyState::Pending(_) => write!(f, "Lazy(pending)"),
+            LazyState::Realized(ref step) => write!(f, "Lazy({:?})", step),
+        }
+    }
+}
+
+// Dropping the head of a long realized sequence would otherwise recurse
+// once per cell, so unlink the chain iteratively.
+impl Drop for Lazy {
+    fn drop(&mut self) {
+        let mut next = match self.state.get_mut() {
+            LazyState::Realized(Some((_, rest))) => mem::replace(rest, Nil),
+            _ => return,
+        };
+        while let LazySeq(cell) = next {
+            next = match Rc::try_unwrap(cell) {
+                Ok(mut lazy) => match lazy.state.get_mut() {
+                    LazyState::Realized(Some((_, rest))) => mem::replace(rest, Nil),
+                    _ => Nil,
+                },
+                Err(_) => Nil,
+            };
+        }
+    }
+}
+
 // type utility macros
 
 macro_rules! list {
   ($seq:expr) => {{
-    List(Rc::new($seq),Rc::new(Nil))
+    List($crate::types::MalVec::from($seq),$crate::shared::Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    List(Rc::new(v),Rc::new(Nil))
+    List($crate::types::MalVec::from(v),$crate::shared::Rc::new(Nil))
   }}
 }
 
 macro_rules! vector {
   ($seq:expr) => {{
-    Vector(Rc::new($seq),Rc::new(Nil))
+    Vector($crate::types::MalVec::from($seq),$crate::shared::Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    Vector(Rc::new(v),Rc::new(Nil))
+    Vector($crate::types::MalVec::from(v),$crate::shared::Rc::new(Nil))
   }}
 }
 
@@ -68,11 +228,178 @@ pub fn error(s: &str) -> MalRet {
     Err(ErrString(s.to_string()))
 }
 
+pub fn type_error(s: &str) -> MalRet {
+    Err(ErrKind("type-error", s.to_string()))
+}
+
+pub fn ex_info(message: String, data: MalVal, cause: MalVal) -> MalVal {
+    Exception(Rc::new(ExInfo {
+        message,
+        data,
+        cause,
+    }))
+}
+
 pub fn format_error(e: MalErr) -> String {
-    match e {
-        ErrString(s) => s.clone(),
-        ErrMalVal(mv) => mv.pr_str(true),
+    e.to_string()
+}
+
+impl fmt::Display for MalErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrString(s) | ErrKind(_, s) => write!(f, "{}", s),
+            ErrMalVal(mv) => write!(f, "{}", mv.pr_str(true)),
+            ErrTrace(e, frames) => match frames.iter().find_map(|f| f.at.as_ref()) {
+                Some(at) => write!(f, "{}: {}", at, e),
+                None => write!(f, "{}", e),
+            },
+        }
+    }
+}
+
+impl std::error::Error for MalErr {}
+
+impl MalErr {
+    /// The value bound by `catch*`: the thrown value or the error message.
+    #[allow(dead_code)]
+    pub fn to_value(&self) -> MalVal {
+        match self {
+            ErrString(s) | ErrKind(_, s) => Str(s.clone()),
+            ErrMalVal(mv) => mv.clone(),
+            ErrTrace(e, _) => e.to_value(),
+        }
+    }
+
+    /// Like `to_value`, but an interpreter error becomes an exception
+    /// whose data holds its `:type`.
+    #[allow(dead_code)]
+    pub fn to_exception(&self) -> MalVal {
+        let (kind, msg) = match self {
+            ErrString(s) => ("error", s),
+            ErrKind(kind, s) => (*kind, s),
+            ErrMalVal(mv) => return mv.clone(),
+            ErrTrace(e, _) => return e.to_exception(),
+        };
+        let data = std::iter::once((keyword("type"), keyword(kind))).collect();
+        ex_info(msg.clone(), Hash(data, Rc::new(Nil)), Nil)
+    }
+
+    pub fn trace(&self) -> &[Frame] {
+        match self {
+            ErrTrace(_, frames) => frames,
+            _ => &[],
+        }
     }
+
+    fn with_frame(self, update: impl FnOnce(&mut Vec<Frame>)) -> MalErr {
+        let (e, mut frames) = match self {
+            ErrTrace(e, frames) => (e, frames),
+            e => (Box::new(e), vec![]),
+        };
+        update(&mut frames);
+        ErrTrace(e, frames)
+    }
+
+    /// Records that the error escaped a form at `at`, unless a more
+    /// precise location is still waiting to be attributed to a function.
+    #[allow(dead_code)]
+    pub fn at(self, at: Option<String>) -> MalErr {
+        match at {
+            Some(_) if self.trace().last().is_none_or(|f| f.name.is_some()) => {
+                self.with_frame(|frames| frames.push(Frame { name: None, at }))
+            }
+            _ => self,
+        }
+    }
+
+    /// Records that the error unwound out of the function `name`, which was
+    /// called from the form at `call_site`.
+    pub fn in_fn(self, name: &str, call_site: Option<String>) -> MalErr {
+        self.with_frame(|frames| {
+            match frames.last_mut() {
+                Some(f) if f.name.is_none() => f.name = Some(Rc::from(name)),
+                _ => frames.push(Frame {
+                    name: Some(Rc::from(name)),
+                    at: None,
+                }),
+            }
+            if call_site.is_some() {
+                frames.push(Frame {
+                    name: None,
+                    at: call_site,
+                })
+            }
+        })
+    }
+}
+
+/// The `file:line:column` recorded by the reader in a list's metadata.
+#[allow(dead_code)]
+pub fn source_location(meta: &MalVal) -> Option<String> {
+    match meta {
+        Hash(hm, _) => match (
+            hm.get(&keyword("file")),
+            hm.get(&keyword("line")),
+            hm.get(&keyword("column")),
+        ) {
+            (Some(Str(f)), Some(Int(l)), Some(Int(c))) => Some(format!("{}:{}:{}", f, l, c)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `max_args` of a builtin that accepts any number of trailing arguments.
+pub const VARIADIC: usize = usize::MAX;
+
+pub fn check_arity(
+    name: &str,
+    min_args: usize,
+    max_args: usize,
+    nargs: usize,
+) -> Result<(), MalErr> {
+    if nargs < min_args || nargs > max_args {
+        return Err(ErrKind(
+            "arity-error",
+            format!("wrong number of args ({}) passed to {}", nargs, name),
+        ));
+    }
+    Ok(())
+}
+
+pub fn bigint(n: num_bigint::BigInt) -> MalVal {
+    BigInt(Rc::new(n))
+}
+
+/// An integer value, as an Int when it fits.
+pub fn integer(n: num_bigint::BigInt) -> MalVal {
+    match n.to_i64() {
+        Some(i) => Int(i),
+        None => bigint(n),
+    }
+}
+
+/// A ratio value in lowest terms; whole ratios become integers.
+pub fn ratio(r: BigRational) -> MalVal {
+    if r.is_integer() {
+        integer(r.to_integer())
+    } else {
+        Ratio(Rc::new(r))
+    }
+}
+
+/// A lazy sequence whose contents are the seq returned by `thunk`.
+pub fn lazy_seq(thunk: impl Fn() -> MalRet + Shareable + 'static) -> MalVal {
+    LazySeq(Rc::new(Lazy {
+        state: RefCell::new(LazyState::Pending(Rc::new(thunk))),
+    }))
+}
+
+/// An already realized sequence cell; `rest` is not forced.
+pub fn lazy_cons(first: MalVal, rest: MalVal) -> MalVal {
+    LazySeq(Rc::new(Lazy {
+        state: RefCell::new(LazyState::Realized(Some((first, rest)))),
+    }))
 }
 
 pub fn atom(mv: &MalVal) -> MalVal {
@@ -80,60 +407,122 @@ pub fn atom(mv: &MalVal) -> MalVal {
 }
 
 impl MalVal {
-    pub fn keyword(&self) -> MalRet {
+    /// Splits a seqable value into its first element and the rest,
+    /// realizing at most one cell of a lazy sequence.
+    pub fn uncons(&self) -> Result<Option<(MalVal, MalVal)>, MalErr> {
         match self {
-            Str(s) if s.starts_with("\u{29e}") => Ok(Str(s.to_string())),
-            Str(s) => Ok(Str(format!("\u{29e}{}", s))),
-            _ => error("invalid type for keyword"),
+            Nil => Ok(None),
+            List(l, _) | Vector(l, _) if l.is_empty() => Ok(None),
+            List(l, _) | Vector(l, _) => Ok(Some((l[0].clone(), list!(l.skip(1))))),
+            Set(s, _) => list!(s.iter().cloned().collect::<MalVec>()).uncons(),
+            LazySeq(cell) => cell.force(),
+            _ => Err(ErrKind(
+                "type-error",
+                format!("don't know how to create seq from {}", self.pr_str(true)),
+            )),
+        }
+    }
+
+    /// All the elements of a seqable value, fully realizing lazy sequences.
+    pub fn realize(&self) -> Result<MalVec, MalErr> {
+        match self {
+            List(l, _) | Vector(l, _) => Ok(l.clone()),
+            _ => {
+                let mut res = MalVec::new();
+                let mut s = self.clone();
+                while let Some((first, rest)) = s.uncons()? {
+                    res.push_back(first);
+                    s = rest;
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    /// The elements of a seqable value in turn, realizing a lazy sequence
+    /// only as far as they are taken.
+    pub fn elements(&self) -> Box<dyn Iterator<Item = MalRet>> {
+        match self {
+            List(l, _) | Vector(l, _) => Box::new(l.clone().into_iter().map(Ok)),
+            Set(s, _) => Box::new(s.clone().into_iter().map(Ok)),
+            _ => {
+                let mut s = self.clone();
+                Box::new(iter::from_fn(move || match s.uncons() {
+                    Ok(Some((first, rest))) => {
+                        s = rest;
+                        Some(Ok(first))
+                    }
+                    Ok(None) => None,
+                    Err(e) => {
+                        s = Nil;
+                        Some(Err(e))
+                    }
+                }))
+            }
         }
     }
 
     pub fn empty_q(&self) -> MalRet {
         match self {
-            List(l, _) | Vector(l, _) => Ok(Bool(l.len() == 0)),
+            List(l, _) | Vector(l, _) => Ok(Bool(l.is_empty())),
+            Set(s, _) => Ok(Bool(s.is_empty())),
+            LazySeq(_) => Ok(Bool(self.uncons()?.is_none())),
             Nil => Ok(Bool(true)),
-            _ => error("invalid type for empty?"),
+            _ => type_error("invalid type for empty?"),
         }
     }
 
     pub fn count(&self) -> MalRet {
         match self {
             List(l, _) | Vector(l, _) => Ok(Int(l.len() as i64)),
+            Set(s, _) => Ok(Int(s.len() as i64)),
+            LazySeq(_) => Ok(Int(self.realize()?.len() as i64)),
             Nil => Ok(Int(0)),
-            _ => error("invalid type for count"),
+            _ => type_error("invalid type for count"),
         }
     }
 
     pub fn apply(&self, args: MalArgs) -> MalRet {
         match *self {
-            Func(f, _) => f(args),
+            Func {
+                ref name,
+                ref f,
+                min_args,
+                max_args,
+                ..
+            } => {
+                check_arity(name, min_args, max_args, args.len())?;
+                (f.0)(args)
+            }
             MalFunc {
                 eval,
                 ref ast,
                 ref env,
                 ref params,
+                ref name,
                 ..
             } => {
                 let a = &**ast;
                 let p = &**params;
                 let fn_env = env_bind(Some(env.clone()), p.clone(), args)?;
-                Ok(eval(a.clone(), fn_env)?)
+                eval(a.clone(), fn_env).map_err(|e| e.in_fn(name.as_deref().unwrap_or("fn*"), None))
             }
-            _ => error("attempt to call non-function"),
-        }
-    }
-
-    pub fn keyword_q(&self) -> bool {
-        match self {
-            Str(s) if s.starts_with("\u{29e}") => true,
-            _ => false,
+            Keyword(_) => {
+                check_arity(&self.pr_str(true), 1, 2, args.len())?;
+                let default = args.get(1).cloned().unwrap_or(Nil);
+                match args[0] {
+                    Hash(ref hm, _) => Ok(hm.get(self).cloned().unwrap_or(default)),
+                    _ => Ok(default),
+                }
+            }
+            _ => type_error("attempt to call non-function"),
         }
     }
 
     pub fn deref(&self) -> MalRet {
         match self {
             Atom(a) => Ok(a.borrow().clone()),
-            _ => error("attempt to deref a non-Atom"),
+            _ => type_error("attempt to deref a non-Atom"),
         }
     }
 
@@ -143,7 +532,7 @@ impl MalVal {
                 *a.borrow_mut() = new.clone();
                 Ok(new.clone())
             }
-            _ => error("attempt to reset! a non-Atom"),
+            _ => type_error("attempt to reset! a non-Atom"),
         }
     }
 
@@ -151,21 +540,23 @@ impl MalVal {
         match self {
             Atom(a) => {
                 let f = &args[0];
-                let mut fargs = args[1..].to_vec();
-                fargs.insert(0, a.borrow().clone());
-                *a.borrow_mut() = f.apply(fargs)?;
-                Ok(a.borrow().clone())
+                shared::update(a, |val| {
+                    let mut fargs = args[1..].to_vec();
+                    fargs.insert(0, val);
+                    f.apply(fargs)
+                })
             }
-            _ => error("attempt to swap! a non-Atom"),
+            _ => type_error("attempt to swap! a non-Atom"),
         }
     }
 
     pub fn get_meta(&self) -> MalRet {
         match self {
-            List(_, meta) | Vector(_, meta) | Hash(_, meta) => Ok((&**meta).clone()),
-            Func(_, meta) => Ok((&**meta).clone()),
-            MalFunc { meta, .. } => Ok((&**meta).clone()),
-            _ => error("meta not supported by type"),
+            List(_, meta) | Vector(_, meta) | Hash(_, meta) | Set(_, meta) => Ok((**meta).clone()),
+            Func { meta, .. } => Ok((**meta).clone()),
+            MalFunc { meta, .. } => Ok((**meta).clone()),
+            HostObject { meta, .. } => Ok((**meta).clone()),
+            _ => type_error("meta not supported by type"),
         }
     }
 
@@ -174,11 +565,13 @@ impl MalVal {
             List(_, ref mut meta)
             | Vector(_, ref mut meta)
             | Hash(_, ref mut meta)
-            | Func(_, ref mut meta)
-            | MalFunc { ref mut meta, .. } => {
-                *meta = Rc::new((&*new_meta).clone());
+            | Set(_, ref mut meta)
+            | Func { ref mut meta, .. }
+            | MalFunc { ref mut meta, .. }
+            | HostObject { ref mut meta, .. } => {
+                *meta = Rc::new((*new_meta).clone());
             }
-            _ => return error("with-meta not supported by type"),
+            _ => return type_error("with-meta not supported by type"),
         };
         Ok(self.clone())
     }
@@ -190,51 +583,251 @@ impl PartialEq for MalVal {
             (Nil, Nil) => true,
             (Bool(ref a), Bool(ref b)) => a == b,
             (Int(ref a), Int(ref b)) => a == b,
+            (BigInt(ref a), BigInt(ref b)) => a == b,
+            (Int(a), BigInt(b)) | (BigInt(b), Int(a)) => num_bigint::BigInt::from(*a) == **b,
+            (Ratio(ref a), Ratio(ref b)) => a == b,
+            (Float(ref a), Float(ref b)) => a == b,
             (Str(ref a), Str(ref b)) => a == b,
             (Sym(ref a), Sym(ref b)) => a == b,
+            (Keyword(ref a), Keyword(ref b)) => a == b,
             (List(ref a, _), List(ref b, _))
             | (Vector(ref a, _), Vector(ref b, _))
             | (List(ref a, _), Vector(ref b, _))
             | (Vector(ref a, _), List(ref b, _)) => a == b,
             (Hash(ref a, _), Hash(ref b, _)) => a == b,
+            (Set(ref a, _), Set(ref b, _)) => a == b,
+            (LazySeq(ref a), LazySeq(ref b)) if Rc::ptr_eq(a, b) => true,
+            (LazySeq(_), List(..))
+            | (LazySeq(_), Vector(..))
+            | (LazySeq(_), LazySeq(_))
+            | (List(..), LazySeq(_))
+            | (Vector(..), LazySeq(_)) => {
+                // element by element, so that comparing with an infinite
+                // sequence stops at the first difference
+                let (mut a, mut b) = (self.elements(), other.elements());
+                loop {
+                    match (a.next(), b.next()) {
+                        (None, None) => return true,
+                        (Some(Ok(x)), Some(Ok(y))) if x == y => {}
+                        _ => return false,
+                    }
+                }
+            }
+            (Exception(ref a), Exception(ref b)) => {
+                a.message == b.message && a.data == b.data && a.cause == b.cause
+            }
             (MalFunc { .. }, MalFunc { .. }) => false,
+            (HostObject { obj: ref a, .. }, HostObject { obj: ref b, .. }) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 }
 
-pub fn func(f: fn(MalArgs) -> MalRet) -> MalVal {
-    Func(f, Rc::new(Nil))
+// Maps need keys to be equal to themselves, which rules out functions,
+// atoms and collections containing them.
+impl Eq for MalVal {}
+
+// Sequences hash only their first elements, so that lists, vectors and lazy
+// sequences that are `=` hash alike, and an infinite sequence can be hashed.
+const HASHED_ELEMENTS: usize = 32;
+
+impl std::hash::Hash for MalVal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Nil => 0u8.hash(state),
+            Bool(b) => b.hash(state),
+            Int(i) => i.hash(state),
+            // Hashes must agree with Int for the values `=` to an Int
+            BigInt(n) => match n.to_i64() {
+                Some(i) => i.hash(state),
+                None => n.hash(state),
+            },
+            Ratio(r) => r.hash(state),
+            Float(f) if *f == 0.0 => 0f64.to_bits().hash(state),
+            Float(f) => f.to_bits().hash(state),
+            Str(s) | Sym(s) => s.hash(state),
+            Keyword(k) => k.hash(state),
+            List(..) | Vector(..) | LazySeq(_) => {
+                for v in self.elements().take(HASHED_ELEMENTS) {
+                    match v {
+                        Ok(v) => v.hash(state),
+                        Err(_) => break,
+                    }
+                }
+            }
+            Hash(hm, _) => unordered_hash(hm.iter()).hash(state),
+            Set(s, _) => unordered_hash(s.iter()).hash(state),
+            Exception(ex) => ex.message.hash(state),
+            HostObject { obj, .. } => (Rc::as_ptr(obj) as *const () as usize).hash(state),
+            Func { .. } | MalFunc { .. } | Atom(_) => {}
+        }
+    }
+}
+
+// Equal maps and sets may iterate in different orders, so entries are
+// combined with a commutative sum.
+fn unordered_hash<T: std::hash::Hash>(entries: impl Iterator<Item = T>) -> u64 {
+    let mut sum = 0u64;
+    for entry in entries {
+        let mut h = FnvHasher::default();
+        entry.hash(&mut h);
+        sum = sum.wrapping_add(h.finish());
+    }
+    sum
+}
+
+impl MalVal {
+    pub fn hashable(&self) -> bool {
+        match self {
+            Func { .. } | MalFunc { .. } | Atom(_) => false,
+            List(l, _) | Vector(l, _) => l.iter().all(|v| v.hashable()),
+            Hash(hm, _) => hm.iter().all(|(k, v)| k.hashable() && v.hashable()),
+            // checked only as far as it is hashed
+            LazySeq(_) => self
+                .elements()
+                .take(HASHED_ELEMENTS)
+                .all(|v| v.is_ok_and(|v| v.hashable())),
+            Exception(ex) => ex.data.hashable() && ex.cause.hashable(),
+            _ => true,
+        }
+    }
+}
+
+pub fn func(
+    name: &'static str,
+    min_args: usize,
+    max_args: usize,
+    f: fn(MalArgs) -> MalRet,
+) -> MalVal {
+    native_fn(name, min_args, max_args, f)
+}
+
+/// A builtin backed by a Rust closure, which may capture state of its own.
+pub fn native_fn(
+    name: &str,
+    min_args: usize,
+    max_args: usize,
+    f: impl Fn(MalArgs) -> MalRet + Shareable + 'static,
+) -> MalVal {
+    Func {
+        name: Rc::from(name),
+        f: NativeFn(Rc::new(f)),
+        min_args,
+        max_args,
+        meta: Rc::new(Nil),
+    }
+}
+
+/// Wraps `obj` so that it can be passed through mal code; builtins
+/// registered by the host get it back with `MalVal::downcast_ref`.
+///
+/// ```
+/// use mal::types::{host_object, MalVal};
+/// use mal::Interpreter;
+///
+/// struct Counter(u32);
+///
+/// let mal = Interpreter::new();
+/// mal.define("counter", host_object(Counter(7)));
+/// mal.define_fn("count-of", 1, 1, |a| match a[0].downcast_ref::<Counter>() {
+///     Some(c) => Ok(MalVal::Int(c.0.into())),
+///     None => Ok(MalVal::Nil),
+/// });
+/// assert_eq!(mal.eval_str("(count-of counter)").unwrap(), MalVal::Int(7));
+/// assert_eq!(mal.eval_str("(= counter counter)").unwrap(), MalVal::Bool(true));
+/// mal.define("other", host_object(Counter(7)));
+/// assert_eq!(mal.eval_str("(= counter other)").unwrap(), MalVal::Bool(false));
+/// assert_eq!(mal.eval_str("(:id (meta (with-meta counter {:id 1})))").unwrap(), MalVal::Int(1));
+/// assert_eq!(mal.eval_str("(pr-str counter)").unwrap().pr_str(false), "#<host Counter>");
+/// ```
+#[allow(dead_code)]
+pub fn host_object<T: Any + Shareable>(obj: T) -> MalVal {
+    HostObject {
+        obj: Rc::new(obj),
+        type_name: std::any::type_name::<T>(),
+        meta: Rc::new(Nil),
+    }
+}
+
+impl MalVal {
+    /// The host object wrapped in this value, if it is a `T`.
+    #[allow(dead_code)]
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            HostObject { obj, .. } => obj.downcast_ref::<T>(),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    static KEYWORDS: RefCell<FnvHashSet<Rc<str>>> = RefCell::new(FnvHashSet::default());
+}
+
+/// The interned keyword `:name`, where `name` may be namespaced as
+/// `ns/name`.
+pub fn keyword(name: &str) -> MalVal {
+    KEYWORDS.with(|kws| {
+        let mut kws = kws.borrow_mut();
+        if let Some(k) = kws.get(name) {
+            return Keyword(k.clone());
+        }
+        let k: Rc<str> = Rc::from(name);
+        kws.insert(k.clone());
+        Keyword(k)
+    })
+}
+
+/// The names of all keywords read or created so far.
+#[allow(dead_code)]
+pub fn interned_keywords() -> Vec<Rc<str>> {
+    KEYWORDS.with(|kws| kws.borrow().iter().cloned().collect())
 }
 
-pub fn _assoc(mut hm: FnvHashMap<String, MalVal>, kvs: MalArgs) -> MalRet {
-    if kvs.len() % 2 != 0 {
+pub fn _assoc(mut hm: MalMap, kvs: MalArgs) -> MalRet {
+    if !kvs.len().is_multiple_of(2) {
         return error("odd number of elements");
     }
     for (k, v) in kvs.iter().tuples() {
-        match k {
-            Str(s) => {
-                hm.insert(s.to_string(), v.clone());
-            }
-            _ => return error("key is not string"),
+        if !k.hashable() {
+            return error("key is not hashable");
         }
+        hm.insert(k.clone(), v.clone());
     }
-    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+    Ok(Hash(hm, Rc::new(Nil)))
 }
 
-pub fn _dissoc(mut hm: FnvHashMap<String, MalVal>, ks: MalArgs) -> MalRet {
+pub fn _dissoc(mut hm: MalMap, ks: MalArgs) -> MalRet {
     for k in ks.iter() {
-        match k {
-            Str(ref s) => {
-                hm.remove(s);
-            }
-            _ => return error("key is not string"),
-        }
+        hm.remove(k);
     }
-    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+    Ok(Hash(hm, Rc::new(Nil)))
+}
+
+#[allow(dead_code)]
+pub fn list(vs: MalArgs) -> MalVal {
+    list!(vs)
+}
+
+#[allow(dead_code)]
+pub fn vector(vs: MalArgs) -> MalVal {
+    vector!(vs)
 }
 
 pub fn hash_map(kvs: MalArgs) -> MalRet {
-    let hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
-    _assoc(hm, kvs)
+    _assoc(MalMap::default(), kvs)
+}
+
+pub fn _conj_set(mut s: MalSet, vs: MalArgs) -> MalRet {
+    for v in vs {
+        if !v.hashable() {
+            return error("set element is not hashable");
+        }
+        s.insert(v);
+    }
+    Ok(Set(s, Rc::new(Nil)))
+}
+
+pub fn hash_set(vs: MalArgs) -> MalRet {
+    _conj_set(MalSet::default(), vs)
 }